@@ -1,10 +1,33 @@
 #!/bin/bash
 set -e
 
-# First argument is the command (compile or run)
+# First argument is the command (fetch, compile, run or serve)
 ACTION=$1
 
 case $ACTION in  
+  fetch)
+    # Download dependencies; the only step that needs the network
+    PROJECT_DIR=$2
+    cd $PROJECT_DIR
+    echo "Fetching dependencies in: $PROJECT_DIR" >&2
+    
+    shift 2  # Remove the action and project_dir arguments
+    cargo fetch "$@"
+    exit $?
+    ;;
+    
+  compile)
+    # Build offline, reporting artifacts and diagnostics as JSON on stdout;
+    # profile, features and target come with the remaining arguments
+    PROJECT_DIR=$2
+    cd $PROJECT_DIR
+    echo "Compiling project with cargo build in: $PROJECT_DIR" >&2
+    
+    shift 2  # Remove the action and project_dir arguments
+    cargo build --offline --message-format=json "$@"
+    exit $?
+    ;;
+    
   run)
     # Second argument is the project directory
     PROJECT_DIR=$2
@@ -55,7 +78,7 @@ case $ACTION in
     ;;
     
   *)
-    echo "Usage: $0 compile project_directory OR $0 run project_directory [args...] OR $0 serve"
+    echo "Usage: $0 fetch project_directory OR $0 compile project_directory OR $0 run project_directory [args...] OR $0 serve"
     exit 1
     ;;
 esac 
\ No newline at end of file
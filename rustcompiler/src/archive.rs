@@ -0,0 +1,620 @@
+// Safe extraction of uploaded project archives
+//
+// Every entry is checked before anything is written: no absolute paths, no
+// `..` components, no writing through symlinks, symlink targets must be
+// relative without `..`, and device files or setuid/setgid bits are refused.
+// Cargo configuration directories are refused as well: cargo runs on the host
+// to fetch dependencies and would honor settings such as build.rustc-wrapper.
+// Ownership from the archive is ignored; only permission bits are kept.
+//
+// Extraction streams from any reader. The format is detected from the first
+// bytes, and the number of entries, each file's size and the total size are
+// capped on the decompressed data, so compression bombs stop early.
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    Malformed,
+    UnsupportedFormat,
+    AbsolutePath,
+    ParentComponent,
+    SymlinkEscape,
+    HardlinkEscape,
+    CargoConfig,
+    DeviceFile,
+    SetuidBit,
+    UnsupportedEntry,
+    TooManyEntries,
+    FileTooLarge,
+    TotalSizeExceeded,
+}
+
+impl RejectReason {
+    // Rejections for exceeding the extraction caps rather than bad content
+    pub fn is_size_limit(self) -> bool {
+        matches!(self, RejectReason::TooManyEntries | RejectReason::FileTooLarge | RejectReason::TotalSizeExceeded)
+    }
+}
+
+#[derive(Debug)]
+pub enum ExtractError {
+    // The archive is unsafe or invalid; the client's fault
+    Rejected { reason: RejectReason, entry: String, message: String },
+    // Writing the extracted files failed
+    Io(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    TarGz,
+    TarZst,
+    TarXz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // Bytes needed to tell the formats apart (the ustar magic sits at 257)
+    pub const DETECT_LEN: usize = 262;
+
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZst)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveFormat::TarXz)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.len() >= Self::DETECT_LEN && &header[257..262] == b"ustar" {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExtractLimits {
+    pub max_total_bytes: u64,
+    pub max_entries: u64,
+    pub max_file_bytes: u64,
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    std::env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+impl ExtractLimits {
+    pub fn from_env() -> Self {
+        Self {
+            max_total_bytes: env_u64("EXTRACT_MAX_BYTES").unwrap_or(256 * 1024 * 1024),
+            max_entries: env_u64("EXTRACT_MAX_ENTRIES").unwrap_or(10_000),
+            max_file_bytes: env_u64("EXTRACT_MAX_FILE_BYTES").unwrap_or(64 * 1024 * 1024),
+        }
+    }
+}
+
+fn reject(reason: RejectReason, entry: &Path, message: String) -> ExtractError {
+    ExtractError::Rejected {
+        reason,
+        entry: entry.display().to_string(),
+        message,
+    }
+}
+
+fn malformed(error: impl std::fmt::Display) -> ExtractError {
+    reject(RejectReason::Malformed, Path::new(""), format!("Invalid archive: {}", error))
+}
+
+// Turns an archive path into a relative path made of normal components only.
+// `link_of` is set when checking the target of that hard link entry.
+fn sanitize(path: &Path, link_of: Option<&Path>) -> Result<PathBuf, ExtractError> {
+    let fail = |reason: RejectReason, problem: &str| match link_of {
+        Some(entry) => reject(
+            RejectReason::HardlinkEscape,
+            entry,
+            format!("Hard link '{}' targets '{}', which {}", entry.display(), path.display(), problem),
+        ),
+        None => reject(reason, path, format!("Entry '{}' {}", path.display(), problem)),
+    };
+
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return Err(fail(RejectReason::AbsolutePath, "is an absolute path")),
+            Component::ParentDir => return Err(fail(RejectReason::ParentComponent, "contains a '..' component")),
+        }
+    }
+    Ok(clean)
+}
+
+// Refuses paths whose parent directories include a symlink, which could
+// redirect the write outside the root
+fn check_no_symlink_parents(root: &Path, relative: &Path, entry: &Path) -> Result<(), ExtractError> {
+    let mut current = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        if components.peek().is_none() {
+            break;
+        }
+        current.push(component);
+        if fs::symlink_metadata(&current).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            return Err(reject(
+                RejectReason::SymlinkEscape,
+                entry,
+                format!("Entry '{}' is written through the symlink '{}'", entry.display(), current.strip_prefix(root).unwrap_or(&current).display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Symlinks may only point below their own directory. Any `..` is refused:
+// checked lexically it could still climb out through another link, as with
+// `a/b/s -> ../..` followed by `a/b/t -> s/..`.
+fn check_symlink_target(target: &Path, entry: &Path) -> Result<(), ExtractError> {
+    let escapes = target.components().any(|component| {
+        matches!(component, Component::ParentDir | Component::RootDir | Component::Prefix(_))
+    });
+    if escapes || target.as_os_str().is_empty() {
+        return Err(reject(
+            RejectReason::SymlinkEscape,
+            entry,
+            format!("Symlink '{}' must point below its own directory, not to '{}'", entry.display(), target.display()),
+        ));
+    }
+    Ok(())
+}
+
+// Reads a file of an extracted project on the host, refusing to follow a
+// symlink in its place
+pub fn read_no_follow(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// Removes whatever is at `dest` unless it is a real directory, so writes never
+// follow a symlink left there earlier
+fn clear_destination(dest: &Path) -> Result<(), ExtractError> {
+    match fs::symlink_metadata(dest) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => fs::remove_file(dest).map_err(|e| ExtractError::Io(format!("Failed to replace {}: {}", dest.display(), e))),
+        Err(_) => Ok(()),
+    }
+}
+
+fn io_error(context: &str, path: &Path, error: std::io::Error) -> ExtractError {
+    ExtractError::Io(format!("{} {}: {}", context, path.display(), error))
+}
+
+// Writes checked entries below the root and keeps count against the limits
+struct Extractor<'a> {
+    root: &'a Path,
+    limits: &'a ExtractLimits,
+    entries: u64,
+    total_bytes: u64,
+}
+
+impl Extractor<'_> {
+    // Validates an entry path and prepares its parent directory. Returns None
+    // for entries that name the root itself.
+    fn begin(&mut self, path: &Path, mode: u32) -> Result<Option<(PathBuf, PathBuf)>, ExtractError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(reject(
+                RejectReason::TooManyEntries,
+                path,
+                format!("Archive has more than {} entries", self.limits.max_entries),
+            ));
+        }
+
+        let relative = sanitize(path, None)?;
+        if relative.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        if relative.components().any(|component| component.as_os_str() == ".cargo") {
+            return Err(reject(
+                RejectReason::CargoConfig,
+                path,
+                format!("Entry '{}' is inside a .cargo directory; uploads cannot configure cargo", path.display()),
+            ));
+        }
+        if mode & 0o6000 != 0 {
+            return Err(reject(RejectReason::SetuidBit, path, format!("Entry '{}' has the setuid or setgid bit set", path.display())));
+        }
+
+        check_no_symlink_parents(self.root, &relative, path)?;
+        let dest = self.root.join(&relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| io_error("Failed to create", parent, e))?;
+        }
+        Ok(Some((relative, dest)))
+    }
+
+    fn directory(&mut self, path: &Path, mode: u32) -> Result<(), ExtractError> {
+        let Some((_, dest)) = self.begin(path, mode)? else {
+            return Ok(());
+        };
+        clear_destination(&dest)?;
+        fs::create_dir_all(&dest).map_err(|e| io_error("Failed to create", &dest, e))
+    }
+
+    fn file(&mut self, path: &Path, mode: u32, contents: &mut dyn Read) -> Result<(), ExtractError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let Some((_, dest)) = self.begin(path, mode)? else {
+            return Ok(());
+        };
+        clear_destination(&dest)?;
+        let mut file = fs::File::create(&dest).map_err(|e| io_error("Failed to create", &dest, e))?;
+
+        // Read one byte past either cap to tell "exactly at" from "over"
+        let remaining = self.limits.max_total_bytes.saturating_sub(self.total_bytes);
+        let allowed = self.limits.max_file_bytes.min(remaining);
+        let written = std::io::copy(&mut contents.take(allowed + 1), &mut file)
+            .map_err(|e| reject(RejectReason::Malformed, path, format!("Failed to read '{}': {}", path.display(), e)))?;
+        if written > allowed {
+            return Err(if written > self.limits.max_file_bytes {
+                reject(
+                    RejectReason::FileTooLarge,
+                    path,
+                    format!("Entry '{}' is larger than {} bytes", path.display(), self.limits.max_file_bytes),
+                )
+            } else {
+                reject(
+                    RejectReason::TotalSizeExceeded,
+                    path,
+                    format!("Archive contents exceed {} bytes", self.limits.max_total_bytes),
+                )
+            });
+        }
+        self.total_bytes += written;
+
+        fs::set_permissions(&dest, fs::Permissions::from_mode(mode & 0o777 | 0o600))
+            .map_err(|e| io_error("Failed to set permissions on", &dest, e))
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> Result<(), ExtractError> {
+        let Some((_, dest)) = self.begin(path, 0)? else {
+            return Ok(());
+        };
+        check_symlink_target(target, path)?;
+        clear_destination(&dest)?;
+        std::os::unix::fs::symlink(target, &dest).map_err(|e| io_error("Failed to create symlink", &dest, e))
+    }
+
+    fn hard_link(&mut self, path: &Path, target: &Path) -> Result<(), ExtractError> {
+        let Some((_, dest)) = self.begin(path, 0)? else {
+            return Ok(());
+        };
+        let target_relative = sanitize(target, Some(path))?;
+        check_no_symlink_parents(self.root, &target_relative, path)?;
+        let source = self.root.join(&target_relative);
+        if !fs::symlink_metadata(&source).map(|m| m.is_file()).unwrap_or(false) {
+            return Err(reject(
+                RejectReason::HardlinkEscape,
+                path,
+                format!("Hard link '{}' must point to a regular file earlier in the archive, not '{}'", path.display(), target.display()),
+            ));
+        }
+        clear_destination(&dest)?;
+        fs::hard_link(&source, &dest).map_err(|e| io_error("Failed to create hard link", &dest, e))
+    }
+}
+
+fn extract_tar(reader: impl Read, extractor: &mut Extractor) -> Result<(), ExtractError> {
+    use tar::EntryType;
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(malformed)? {
+        let mut entry = entry.map_err(malformed)?;
+        // Metadata records such as the pax global header written by git archive
+        let entry_type = entry.header().entry_type();
+        if matches!(entry_type, EntryType::XGlobalHeader | EntryType::XHeader | EntryType::GNULongName | EntryType::GNULongLink) {
+            continue;
+        }
+        let path = entry.path().map_err(malformed)?.into_owned();
+        let mode = entry
+            .header()
+            .mode()
+            .map_err(|e| reject(RejectReason::Malformed, &path, format!("Invalid mode for '{}': {}", path.display(), e)))?;
+
+        let link_target = |entry: &tar::Entry<_>| -> Result<PathBuf, ExtractError> {
+            entry
+                .link_name()
+                .map_err(|e| reject(RejectReason::Malformed, &path, format!("Invalid link target for '{}': {}", path.display(), e)))?
+                .map(|target| target.into_owned())
+                .ok_or_else(|| reject(RejectReason::Malformed, &path, format!("Link '{}' has no target", path.display())))
+        };
+
+        match entry_type {
+            EntryType::Directory => extractor.directory(&path, mode)?,
+            EntryType::Regular | EntryType::Continuous => extractor.file(&path, mode, &mut entry)?,
+            EntryType::Symlink => {
+                let target = link_target(&entry)?;
+                extractor.symlink(&path, &target)?;
+            }
+            EntryType::Link => {
+                let target = link_target(&entry)?;
+                extractor.hard_link(&path, &target)?;
+            }
+            EntryType::Char | EntryType::Block => {
+                return Err(reject(RejectReason::DeviceFile, &path, format!("Entry '{}' is a device file", path.display())));
+            }
+            other => {
+                return Err(reject(
+                    RejectReason::UnsupportedEntry,
+                    &path,
+                    format!("Entry '{}' has unsupported type {:?}", path.display(), other),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Zip needs random access to its central directory, so the compressed
+// archive is buffered in memory first (bounded by the total size cap)
+fn extract_zip(reader: impl Read, extractor: &mut Extractor) -> Result<(), ExtractError> {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+    const S_IFCHR: u32 = 0o020000;
+    const S_IFBLK: u32 = 0o060000;
+
+    let mut buffer = Vec::new();
+    let limit = extractor.limits.max_total_bytes;
+    reader.take(limit + 1).read_to_end(&mut buffer).map_err(malformed)?;
+    if buffer.len() as u64 > limit {
+        return Err(reject(RejectReason::TotalSizeExceeded, Path::new(""), format!("Archive exceeds {} bytes", limit)));
+    }
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).map_err(malformed)?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(malformed)?;
+        let path = PathBuf::from(file.name());
+        let unix_mode = file.unix_mode().unwrap_or(if file.is_dir() { 0o755 } else { 0o644 });
+
+        match unix_mode & S_IFMT {
+            S_IFLNK => {
+                let mut target = String::new();
+                file.read_to_string(&mut target)
+                    .map_err(|e| reject(RejectReason::Malformed, &path, format!("Invalid symlink '{}': {}", path.display(), e)))?;
+                extractor.symlink(&path, Path::new(&target))?;
+            }
+            S_IFCHR | S_IFBLK => {
+                return Err(reject(RejectReason::DeviceFile, &path, format!("Entry '{}' is a device file", path.display())));
+            }
+            _ if file.is_dir() => extractor.directory(&path, unix_mode & 0o7777)?,
+            _ => extractor.file(&path, unix_mode & 0o7777, &mut file)?,
+        }
+    }
+    Ok(())
+}
+
+// Detects the archive format from the first bytes of the stream and extracts
+// it into `root`, which must already exist
+pub fn extract(mut reader: impl Read, root: &Path, limits: &ExtractLimits) -> Result<ArchiveFormat, ExtractError> {
+    let mut header = Vec::with_capacity(ArchiveFormat::DETECT_LEN);
+    (&mut reader)
+        .take(ArchiveFormat::DETECT_LEN as u64)
+        .read_to_end(&mut header)
+        .map_err(malformed)?;
+    let format = ArchiveFormat::detect(&header).ok_or_else(|| {
+        reject(
+            RejectReason::UnsupportedFormat,
+            Path::new(""),
+            "Unsupported archive format, expected .tar.gz, .tar.zst, .tar.xz, .tar or .zip".to_string(),
+        )
+    })?;
+
+    let reader = Cursor::new(header).chain(reader);
+    let mut extractor = Extractor {
+        root,
+        limits,
+        entries: 0,
+        total_bytes: 0,
+    };
+    match format {
+        ArchiveFormat::TarGz => extract_tar(flate2::read::GzDecoder::new(reader), &mut extractor)?,
+        ArchiveFormat::TarZst => extract_tar(zstd::stream::read::Decoder::new(reader).map_err(malformed)?, &mut extractor)?,
+        ArchiveFormat::TarXz => extract_tar(xz2::read::XzDecoder::new(reader), &mut extractor)?,
+        ArchiveFormat::Tar => extract_tar(reader, &mut extractor)?,
+        ArchiveFormat::Zip => extract_zip(reader, &mut extractor)?,
+    }
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Entry<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    // Builds a tar by hand so that paths the tar crate would refuse to write
+    // (absolute, `..`) can be tested too
+    fn tar(entries: &[Entry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for entry in entries {
+            let mut header = tar::Header::new_ustar();
+            let (path, link, data): (&str, Option<&str>, &[u8]) = match entry {
+                Entry::File(path, data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (path, None, data)
+                }
+                Entry::Dir(path) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    (path, None, &[])
+                }
+                Entry::Symlink(path, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    (path, Some(target), &[])
+                }
+            };
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            if let Some(link) = link {
+                header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    // A fresh extraction root inside a directory holding a file outside it
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::write(dir.join("SECRET"), "outside").unwrap();
+        dir
+    }
+
+    fn limits() -> ExtractLimits {
+        ExtractLimits { max_total_bytes: 1024, max_entries: 10, max_file_bytes: 100 }
+    }
+
+    fn extract_entries(name: &str, entries: &[Entry]) -> (PathBuf, Result<ArchiveFormat, ExtractError>) {
+        let dir = scratch(name);
+        let result = extract(Cursor::new(tar(entries)), &dir.join("root"), &limits());
+        (dir, result)
+    }
+
+    fn rejection(result: Result<ArchiveFormat, ExtractError>) -> RejectReason {
+        match result {
+            Err(ExtractError::Rejected { reason, .. }) => reason,
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extracts_files_and_inner_symlinks() {
+        let (dir, result) = extract_entries("ok", &[
+            Entry::Dir("src"),
+            Entry::File("src/main.rs", b"fn main() {}"),
+            Entry::Symlink("main.rs", "src/main.rs"),
+        ]);
+        assert_eq!(result.unwrap(), ArchiveFormat::Tar);
+        assert_eq!(fs::read_to_string(dir.join("root/main.rs")).unwrap(), "fn main() {}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_chained_symlink_escape() {
+        let (dir, result) = extract_entries("chained", &[
+            Entry::Dir("a/b"),
+            Entry::Symlink("a/b/s", "../.."),
+            Entry::Symlink("a/b/t", "s/.."),
+        ]);
+        assert_eq!(rejection(result), RejectReason::SymlinkEscape);
+        assert!(fs::read_to_string(dir.join("root/a/b/t/SECRET")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_symlinks_leaving_their_directory() {
+        for target in ["../x", "a/../../x", "/etc/passwd"] {
+            let (dir, result) = extract_entries("escape", &[Entry::Symlink("a", target)]);
+            assert_eq!(rejection(result), RejectReason::SymlinkEscape, "target {:?}", target);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_writes_through_symlinks() {
+        let (dir, result) = extract_entries("through", &[
+            Entry::Dir("d"),
+            Entry::Symlink("s", "d"),
+            Entry::File("s/file", b"x"),
+        ]);
+        assert_eq!(rejection(result), RejectReason::SymlinkEscape);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        let (dir, result) = extract_entries("parent", &[Entry::File("../SECRET", b"x")]);
+        assert_eq!(rejection(result), RejectReason::ParentComponent);
+        assert_eq!(fs::read_to_string(dir.join("SECRET")).unwrap(), "outside");
+        fs::remove_dir_all(dir).unwrap();
+
+        let (dir, result) = extract_entries("absolute", &[Entry::File("/tmp/x", b"x")]);
+        assert_eq!(rejection(result), RejectReason::AbsolutePath);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn enforces_size_and_entry_limits() {
+        let big = [0u8; 101];
+        let (dir, result) = extract_entries("file-size", &[Entry::File("big", &big)]);
+        assert_eq!(rejection(result), RejectReason::FileTooLarge);
+        fs::remove_dir_all(dir).unwrap();
+
+        let exact = [0u8; 100];
+        let (dir, result) = extract_entries("exact-size", &[Entry::File("exact", &exact)]);
+        assert!(result.is_ok());
+        fs::remove_dir_all(dir).unwrap();
+
+        let chunk = [0u8; 100];
+        let dir = scratch("total");
+        let files = tar(&[Entry::File("a", &chunk), Entry::File("b", &chunk), Entry::File("c", &chunk)]);
+        let small = ExtractLimits { max_total_bytes: 250, ..limits() };
+        let result = extract(Cursor::new(files), &dir.join("root"), &small);
+        assert_eq!(rejection(result), RejectReason::TotalSizeExceeded);
+        fs::remove_dir_all(dir).unwrap();
+
+        let names: Vec<String> = (0..11).map(|index| format!("f{}", index)).collect();
+        let empty: Vec<Entry> = names.iter().map(|name| Entry::File(name, b"")).collect();
+        let (dir, result) = extract_entries("entries", &empty);
+        assert_eq!(rejection(result), RejectReason::TooManyEntries);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_cargo_config() {
+        for path in [".cargo/config.toml", "member/.cargo/config", ".cargo"] {
+            let (dir, result) = extract_entries("cargo-config", &[Entry::File(path, b"[build]\nrustc-wrapper = \"./wrap.sh\"\n")]);
+            assert_eq!(rejection(result), RejectReason::CargoConfig, "path {:?}", path);
+            fs::remove_dir_all(dir).unwrap();
+        }
+        let (dir, result) = extract_entries("cargo-link", &[Entry::Dir("cfg"), Entry::Symlink(".cargo", "cfg")]);
+        assert_eq!(rejection(result), RejectReason::CargoConfig);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let dir = scratch("format");
+        let result = extract(Cursor::new(b"not an archive".to_vec()), &dir.join("root"), &limits());
+        assert_eq!(rejection(result), RejectReason::UnsupportedFormat);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_without_following_symlinks() {
+        let dir = scratch("nofollow");
+        std::os::unix::fs::symlink(dir.join("SECRET"), dir.join("root/link")).unwrap();
+        assert!(read_no_follow(&dir.join("root/link")).is_err());
+        assert_eq!(read_no_follow(&dir.join("SECRET")).unwrap(), "outside");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
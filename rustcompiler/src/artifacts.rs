@@ -0,0 +1,278 @@
+// Cache of compiled binaries keyed by the hash of their source tree
+//
+// The source hash only depends on the relative paths, contents, executable
+// bits and symlink targets under the project, visited in sorted order, so the
+// same sources give the same key whatever archive format, entry order,
+// timestamps or owners they were uploaded with. The top-level target/
+// directory is build output and is left out.
+
+use std::ffi::OsStr;
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const BINARY_FILE: &str = "bin";
+const INFO_FILE: &str = "build.json";
+
+fn hash_tree(hasher: &mut Sha256, root: &Path, relative: &Path, skip: &[&str]) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut entries = fs::read_dir(root.join(relative))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+    for name in entries {
+        let path = relative.join(&name);
+        if relative.as_os_str().is_empty() && (name == "target" || skip.iter().any(|skipped| OsStr::new(skipped) == name)) {
+            continue;
+        }
+        let full = root.join(&path);
+        let metadata = fs::symlink_metadata(&full)?;
+        let path_bytes = path.as_os_str().as_bytes();
+
+        if metadata.file_type().is_symlink() {
+            hasher.update(b"l\0");
+            hasher.update(path_bytes);
+            hasher.update(b"\0");
+            hasher.update(fs::read_link(&full)?.as_os_str().as_bytes());
+            hasher.update(b"\0");
+        } else if metadata.is_dir() {
+            hasher.update(b"d\0");
+            hasher.update(path_bytes);
+            hasher.update(b"\0");
+            hash_tree(hasher, root, &path, skip)?;
+        } else if metadata.is_file() {
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            hasher.update(if executable { b"x\0" } else { b"f\0" });
+            hasher.update(path_bytes);
+            hasher.update(b"\0");
+            hasher.update(metadata.len().to_le_bytes());
+            hash_file(hasher, &full)?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path) -> io::Result<()> {
+    let mut file = fs::File::open(path)?;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+// SHA-256 of a single file, e.g. a built executable
+pub fn file_sha256(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hash_file(&mut hasher, path)?;
+    Ok(hasher.finalize().into())
+}
+
+// SHA-256 of the normalized source tree under `root`
+pub fn source_hash(root: &Path) -> io::Result<[u8; 32]> {
+    source_hash_skipping(root, &[])
+}
+
+// Source hash that also leaves out the given top-level entries, e.g. a
+// Cargo.lock that cargo generated during the build
+pub fn source_hash_skipping(root: &Path, skip: &[&str]) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"walrustee/source-tree/v1\0");
+    hash_tree(&mut hasher, root, Path::new(""), skip)?;
+    Ok(hasher.finalize().into())
+}
+
+// Cache key for a build of the sources with non-default settings, such as a
+// reproducible build. The default build is keyed by the source hash itself.
+pub fn variant_key(source_sha256: &[u8; 32], variant: &str) -> [u8; 32] {
+    if variant.is_empty() {
+        return *source_sha256;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(b"walrustee/build-variant/v1\0");
+    hasher.update(source_sha256);
+    hasher.update(variant.as_bytes());
+    hasher.finalize().into()
+}
+
+// Parses a hex SHA-256 as given back to clients
+pub fn parse_digest(value: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(value).map_err(|_| format!("Invalid source_sha256: '{}' is not hex", value))?;
+    bytes.try_into().map_err(|_| "Invalid source_sha256: expected 64 hex characters".to_string())
+}
+
+// What a cache entry was built from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BuildInfo {
+    pub source_sha256: String,
+    pub binary_name: String,
+    pub toolchain: String,
+    pub built_at: String,
+    // Cargo.lock after the build; entries from before it was recorded lack it
+    #[serde(default)]
+    pub lockfile_sha256: Option<String>,
+    // SHA-256 of the executable, stable across machines for reproducible builds
+    #[serde(default)]
+    pub binary_sha256: String,
+    #[serde(default)]
+    pub reproducible: bool,
+    // `rustc -Vv` of the toolchain
+    #[serde(default)]
+    pub rustc_version: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedBuild {
+    pub binary: PathBuf,
+    pub info: BuildInfo,
+}
+
+pub struct ArtifactCache {
+    dir: PathBuf,
+}
+
+impl ArtifactCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // ARTIFACT_CACHE_DIR, default ./artifacts
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("ARTIFACT_CACHE_DIR").unwrap_or_else(|_| "./artifacts".to_string()))
+    }
+
+    fn entry_dir(&self, key: &[u8; 32]) -> PathBuf {
+        self.dir.join(hex::encode(key))
+    }
+
+    // A cached build under `key` (see variant_key) made with `toolchain`, or
+    // with any toolchain when it is None, if there is one
+    pub fn lookup(&self, key: &[u8; 32], toolchain: Option<&str>) -> Option<CachedBuild> {
+        let dir = self.entry_dir(key);
+        let info: BuildInfo = serde_json::from_slice(&fs::read(dir.join(INFO_FILE)).ok()?).ok()?;
+        let binary = fs::canonicalize(dir.join(BINARY_FILE)).ok()?;
+        (toolchain.is_none_or(|toolchain| info.toolchain == toolchain) && binary.is_file()).then_some(CachedBuild { binary, info })
+    }
+
+    // Copies a freshly built binary into the cache. The entry is assembled
+    // next to its final place and renamed in, so readers never see half of it.
+    pub fn store(&self, key: &[u8; 32], binary: &Path, info: BuildInfo) -> io::Result<CachedBuild> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = self.entry_dir(key);
+        fs::create_dir_all(&self.dir)?;
+        let staging = self.dir.join(format!(".{}.{}", hex::encode(key), uuid::Uuid::new_v4()));
+        fs::create_dir(&staging)?;
+
+        let result = (|| {
+            fs::copy(binary, staging.join(BINARY_FILE))?;
+            fs::set_permissions(staging.join(BINARY_FILE), fs::Permissions::from_mode(0o755))?;
+            fs::write(staging.join(INFO_FILE), serde_json::to_vec_pretty(&info)?)?;
+            // Replaces a stale entry, e.g. one built with another toolchain
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+            match fs::rename(&staging, &dir) {
+                // A concurrent build of the same sources got there first
+                Err(_) if dir.join(INFO_FILE).is_file() => fs::remove_dir_all(&staging),
+                result => result,
+            }
+        })();
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        result?;
+
+        Ok(CachedBuild {
+            binary: fs::canonicalize(dir.join(BINARY_FILE))?,
+            info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    // A small package in a fresh directory
+    fn project(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("artifacts-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn source_hash_ignores_build_output_and_metadata() {
+        let dir = project("ignored");
+        let base = source_hash(&dir).unwrap();
+
+        fs::create_dir_all(dir.join("target/release")).unwrap();
+        fs::write(dir.join("target/release/app"), "binary").unwrap();
+        let file = fs::File::options().append(true).open(dir.join("src/main.rs")).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(source_hash(&dir).unwrap(), base);
+
+        // Only the top-level target/ is build output
+        fs::create_dir_all(dir.join("src/target")).unwrap();
+        fs::write(dir.join("src/target/data.rs"), "").unwrap();
+        assert_ne!(source_hash(&dir).unwrap(), base);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn source_hash_covers_contents_paths_and_exec_bits() {
+        let dir = project("covered");
+        let base = source_hash(&dir).unwrap();
+
+        fs::write(dir.join("src/main.rs"), "fn main() { }\n").unwrap();
+        let edited = source_hash(&dir).unwrap();
+        assert_ne!(edited, base);
+
+        fs::set_permissions(dir.join("src/main.rs"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_ne!(source_hash(&dir).unwrap(), edited);
+        fs::set_permissions(dir.join("src/main.rs"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(source_hash(&dir).unwrap(), edited);
+
+        fs::rename(dir.join("src/main.rs"), dir.join("src/lib.rs")).unwrap();
+        assert_ne!(source_hash(&dir).unwrap(), edited);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn generated_lockfiles_can_be_left_out() {
+        let dir = project("lockfile");
+        let uploaded = source_hash(&dir).unwrap();
+
+        // Cargo.lock written by the build, not part of the upload
+        fs::write(dir.join("Cargo.lock"), "version = 4\n").unwrap();
+        assert_eq!(source_hash_skipping(&dir, &["Cargo.lock"]).unwrap(), uploaded);
+        assert_ne!(source_hash(&dir).unwrap(), uploaded);
+
+        // Only at the top level
+        fs::rename(dir.join("Cargo.lock"), dir.join("src/Cargo.lock")).unwrap();
+        assert_ne!(source_hash_skipping(&dir, &["Cargo.lock"]).unwrap(), uploaded);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn variant_keys_keep_builds_apart() {
+        let source = [1; 32];
+        assert_eq!(variant_key(&source, ""), source);
+        assert_ne!(variant_key(&source, "reproducible"), source);
+        assert_ne!(variant_key(&source, "reproducible"), variant_key(&source, "toolchain=1.80.0"));
+        assert_ne!(variant_key(&source, "reproducible"), variant_key(&[2; 32], "reproducible"));
+        assert_eq!(variant_key(&source, "reproducible"), variant_key(&source, "reproducible"));
+    }
+}
@@ -0,0 +1,339 @@
+// Attestation providers
+//
+// A provider turns 64 bytes of report data into a quote that binds that data
+// to the measured TEE. The TDX backend goes through the Linux configfs-tsm
+// report interface; the mock backend produces a deterministic quote with the
+// same layout so that local runs and verifiers exercise the same code paths.
+
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const REPORT_DATA_LEN: usize = 64;
+
+const DEFAULT_TSM_REPORT_PATH: &str = "/sys/kernel/config/tsm/report";
+const EXECUTION_DOMAIN: &[u8] = b"walrustee/execution/v2";
+const CACHED_EXECUTION_DOMAIN: &[u8] = b"walrustee/cached-execution/v1";
+const RECEIPT_KEY_DOMAIN: &[u8] = b"walrustee/receipt-key/v1";
+
+// TDX quote v4 layout
+pub const TDX_QUOTE_VERSION: u16 = 4;
+pub const TDX_TEE_TYPE: u32 = 0x81;
+const QUOTE_HEADER_LEN: usize = 48;
+const TD_REPORT_BODY_LEN: usize = 584;
+const MR_TD_OFFSET: usize = 136;
+const RTMR_OFFSET: usize = 328;
+const REPORT_DATA_OFFSET: usize = 520;
+const MEASUREMENT_LEN: usize = 48;
+
+pub trait AttestationProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn get_quote(&self, report_data: &[u8; REPORT_DATA_LEN]) -> Result<Vec<u8>, String>;
+}
+
+// Program input of an execution besides the args, the way receipts record it:
+// the SHA-256 of stdin, the environment set by the request and the SHA-256
+// of each input file by name
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct ExecutionInputs<'a> {
+    pub stdin_sha256: Option<&'a str>,
+    pub env: &'a BTreeMap<String, String>,
+    pub input_files: &'a BTreeMap<String, String>,
+}
+
+// Code an execution ran, by SHA-256: the uploaded tarball, or for runs of a
+// cached build the source tree it was built from. Both are committed to under
+// their own domain, so one can't pass for the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutedCode {
+    Tarball([u8; 32]),
+    Source([u8; 32]),
+}
+
+// Report data committing to the code, the inputs and the exact stdout of one
+// execution
+pub fn execution_report_data(tarball: &[u8], args: &[String], inputs: &ExecutionInputs, stdout: &[u8]) -> [u8; REPORT_DATA_LEN] {
+    execution_report_data_from_digest(&ExecutedCode::Tarball(Sha256::digest(tarball).into()), args, inputs, stdout)
+}
+
+// Same, for callers that hashed the code already
+pub fn execution_report_data_from_digest(
+    code: &ExecutedCode,
+    args: &[String],
+    inputs: &ExecutionInputs,
+    stdout: &[u8],
+) -> [u8; REPORT_DATA_LEN] {
+    let args_json = serde_json::to_vec(args).unwrap_or_default();
+    let inputs_json = serde_json::to_vec(inputs).unwrap_or_default();
+    let (domain, code_sha256) = match code {
+        ExecutedCode::Tarball(sha256) => (EXECUTION_DOMAIN, sha256),
+        ExecutedCode::Source(sha256) => (CACHED_EXECUTION_DOMAIN, sha256),
+    };
+
+    let mut hasher = Sha512::new();
+    hasher.update(domain);
+    hasher.update(code_sha256);
+    hasher.update(Sha256::digest(&args_json));
+    hasher.update(Sha256::digest(&inputs_json));
+    hasher.update(Sha256::digest(stdout));
+
+    let mut report_data = [0u8; REPORT_DATA_LEN];
+    report_data.copy_from_slice(&hasher.finalize());
+    report_data
+}
+
+// Report data binding the enclave-held receipt signing key
+pub fn receipt_key_report_data(public_key: &[u8]) -> [u8; REPORT_DATA_LEN] {
+    let mut hasher = Sha512::new();
+    hasher.update(RECEIPT_KEY_DOMAIN);
+    hasher.update(public_key);
+
+    let mut report_data = [0u8; REPORT_DATA_LEN];
+    report_data.copy_from_slice(&hasher.finalize());
+    report_data
+}
+
+// Picks the backend from ATTESTATION_PROVIDER (tdx|mock), tdx when unset.
+// The mock quotes attest nothing, so they are only used when asked for by
+// name; a TDX backend without configfs-tsm is a startup error.
+pub fn provider_from_env() -> Result<Box<dyn AttestationProvider>, String> {
+    let tsm_path = std::env::var("TSM_REPORT_PATH").unwrap_or_else(|_| DEFAULT_TSM_REPORT_PATH.to_string());
+
+    match std::env::var("ATTESTATION_PROVIDER").ok().as_deref() {
+        None | Some("tdx") if PathBuf::from(&tsm_path).is_dir() => Ok(Box::new(TdxProvider::new(tsm_path))),
+        None | Some("tdx") => Err(format!(
+            "configfs-tsm not found at {}; set TSM_REPORT_PATH, or ATTESTATION_PROVIDER=mock for local runs",
+            tsm_path
+        )),
+        Some("mock") => {
+            println!("WARNING: ATTESTATION_PROVIDER=mock, quotes are NOT produced by a TEE and prove nothing");
+            Ok(Box::new(MockProvider))
+        }
+        Some(other) => Err(format!("Unknown ATTESTATION_PROVIDER '{}'; expected tdx or mock", other)),
+    }
+}
+
+// TDX backend using /sys/kernel/config/tsm/report
+pub struct TdxProvider {
+    report_path: PathBuf,
+    counter: AtomicU64,
+}
+
+impl TdxProvider {
+    pub fn new(report_path: impl Into<PathBuf>) -> Self {
+        Self {
+            report_path: report_path.into(),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    fn read_generation(entry: &std::path::Path) -> Result<u64, String> {
+        let generation = fs::read_to_string(entry.join("generation"))
+            .map_err(|e| format!("Failed to read report generation: {}", e))?;
+        generation
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid report generation '{}': {}", generation.trim(), e))
+    }
+}
+
+impl AttestationProvider for TdxProvider {
+    fn name(&self) -> &'static str {
+        "tdx"
+    }
+
+    fn get_quote(&self, report_data: &[u8; REPORT_DATA_LEN]) -> Result<Vec<u8>, String> {
+        // Each request gets its own report entry so concurrent quotes don't race
+        let entry_name = format!("walrustee-{}-{}", std::process::id(), self.counter.fetch_add(1, Ordering::Relaxed));
+        let entry = self.report_path.join(entry_name);
+
+        fs::create_dir(&entry)
+            .map_err(|e| format!("Failed to create report entry {}: {}", entry.display(), e))?;
+
+        let result = (|| {
+            fs::write(entry.join("inblob"), report_data)
+                .map_err(|e| format!("Failed to write report data: {}", e))?;
+            let generation = Self::read_generation(&entry)?;
+
+            let quote = fs::read(entry.join("outblob"))
+                .map_err(|e| format!("Failed to read quote: {}", e))?;
+
+            // The kernel bumps the generation on every inblob write; a mismatch
+            // means someone else wrote to our entry in between
+            if Self::read_generation(&entry)? != generation {
+                return Err("Report entry was modified while reading the quote".to_string());
+            }
+            Ok(quote)
+        })();
+
+        let _ = fs::remove_dir(&entry);
+        result
+    }
+}
+
+// Deterministic backend for tests and local runs. Produces a TDX v4 shaped
+// quote with fixed measurements and an empty signature section.
+pub struct MockProvider;
+
+impl MockProvider {
+    pub fn mr_td() -> [u8; MEASUREMENT_LEN] {
+        Sha384::digest(b"walrustee-mock-mrtd").into()
+    }
+
+    pub fn rtmr(index: usize) -> [u8; MEASUREMENT_LEN] {
+        Sha384::digest(format!("walrustee-mock-rtmr{}", index).as_bytes()).into()
+    }
+}
+
+impl AttestationProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn get_quote(&self, report_data: &[u8; REPORT_DATA_LEN]) -> Result<Vec<u8>, String> {
+        let mut quote = vec![0u8; QUOTE_HEADER_LEN + TD_REPORT_BODY_LEN + 4];
+        quote[0..2].copy_from_slice(&TDX_QUOTE_VERSION.to_le_bytes());
+        quote[2..4].copy_from_slice(&2u16.to_le_bytes()); // ECDSA-256 attestation key
+        quote[4..8].copy_from_slice(&TDX_TEE_TYPE.to_le_bytes());
+
+        let body = &mut quote[QUOTE_HEADER_LEN..QUOTE_HEADER_LEN + TD_REPORT_BODY_LEN];
+        body[MR_TD_OFFSET..MR_TD_OFFSET + MEASUREMENT_LEN].copy_from_slice(&Self::mr_td());
+        for index in 0..4 {
+            let offset = RTMR_OFFSET + index * MEASUREMENT_LEN;
+            body[offset..offset + MEASUREMENT_LEN].copy_from_slice(&Self::rtmr(index));
+        }
+        body[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN].copy_from_slice(report_data);
+
+        // Trailing u32 signature data length stays zero
+        Ok(quote)
+    }
+}
+
+// Fields of a TDX v4 quote needed to check measurements and report data
+#[derive(Debug, Clone)]
+pub struct TdxQuote {
+    pub version: u16,
+    pub tee_type: u32,
+    pub mr_td: [u8; MEASUREMENT_LEN],
+    pub rtmr: [[u8; MEASUREMENT_LEN]; 4],
+    pub report_data: [u8; REPORT_DATA_LEN],
+}
+
+impl TdxQuote {
+    pub fn parse(quote: &[u8]) -> Result<Self, String> {
+        if quote.len() < QUOTE_HEADER_LEN + TD_REPORT_BODY_LEN {
+            return Err(format!("Quote too short: {} bytes", quote.len()));
+        }
+
+        let version = u16::from_le_bytes([quote[0], quote[1]]);
+        if version != TDX_QUOTE_VERSION {
+            return Err(format!("Unsupported quote version: {}", version));
+        }
+        let tee_type = u32::from_le_bytes([quote[4], quote[5], quote[6], quote[7]]);
+        if tee_type != TDX_TEE_TYPE {
+            return Err(format!("Not a TDX quote, tee type: {:#x}", tee_type));
+        }
+
+        let body = &quote[QUOTE_HEADER_LEN..QUOTE_HEADER_LEN + TD_REPORT_BODY_LEN];
+        let mut mr_td = [0u8; MEASUREMENT_LEN];
+        mr_td.copy_from_slice(&body[MR_TD_OFFSET..MR_TD_OFFSET + MEASUREMENT_LEN]);
+        let mut rtmr = [[0u8; MEASUREMENT_LEN]; 4];
+        for (index, register) in rtmr.iter_mut().enumerate() {
+            let offset = RTMR_OFFSET + index * MEASUREMENT_LEN;
+            register.copy_from_slice(&body[offset..offset + MEASUREMENT_LEN]);
+        }
+        let mut report_data = [0u8; REPORT_DATA_LEN];
+        report_data.copy_from_slice(&body[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + REPORT_DATA_LEN]);
+
+        Ok(Self {
+            version,
+            tee_type,
+            mr_td,
+            rtmr,
+            report_data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARBALL: ExecutedCode = ExecutedCode::Tarball([7; 32]);
+
+    fn report_data(args: &[&str], env: &BTreeMap<String, String>, stdout: &[u8]) -> [u8; REPORT_DATA_LEN] {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let files = BTreeMap::new();
+        let inputs = ExecutionInputs { stdin_sha256: None, env, input_files: &files };
+        execution_report_data_from_digest(&TARBALL, &args, &inputs, stdout)
+    }
+
+    #[test]
+    fn report_data_commits_to_every_part_of_the_execution() {
+        let env = BTreeMap::new();
+        let base = report_data(&["10"], &env, b"55\n");
+        assert_eq!(base, report_data(&["10"], &env, b"55\n"));
+
+        assert_ne!(base, report_data(&["11"], &env, b"55\n"));
+        // Args are hashed as a JSON array, so their boundaries count
+        assert_ne!(report_data(&["1 0"], &env, b"55\n"), report_data(&["1", "0"], &env, b"55\n"));
+        // Stdout counts byte for byte, trailing newline included
+        assert_ne!(base, report_data(&["10"], &env, b"55"));
+        let env_set = BTreeMap::from([("RUST_LOG".to_string(), "info".to_string())]);
+        assert_ne!(base, report_data(&["10"], &env_set, b"55\n"));
+
+        let files = BTreeMap::from([("input.txt".to_string(), "00".repeat(32))]);
+        let stdin = "11".repeat(32);
+        let with_files = ExecutionInputs { stdin_sha256: None, env: &env, input_files: &files };
+        let with_stdin = ExecutionInputs { stdin_sha256: Some(&stdin), env: &env, input_files: &BTreeMap::new() };
+        let args = vec!["10".to_string()];
+        assert_ne!(base, execution_report_data_from_digest(&TARBALL, &args, &with_files, b"55\n"));
+        assert_ne!(base, execution_report_data_from_digest(&TARBALL, &args, &with_stdin, b"55\n"));
+        // The same digest as a source hash is a different execution
+        let no_input = ExecutionInputs { stdin_sha256: None, env: &env, input_files: &BTreeMap::new() };
+        assert_ne!(base, execution_report_data_from_digest(&ExecutedCode::Source([7; 32]), &args, &no_input, b"55\n"));
+    }
+
+    #[test]
+    fn report_data_hashes_the_tarball_the_same_way_as_the_digest() {
+        let env = BTreeMap::new();
+        let files = BTreeMap::new();
+        let inputs = ExecutionInputs { stdin_sha256: None, env: &env, input_files: &files };
+        let args = vec!["10".to_string()];
+        assert_eq!(
+            execution_report_data(b"tarball", &args, &inputs, b"55"),
+            execution_report_data_from_digest(&ExecutedCode::Tarball(Sha256::digest(b"tarball").into()), &args, &inputs, b"55"),
+        );
+        // Different domains, so an execution can't pass for a key binding
+        assert_ne!(receipt_key_report_data(b"55"), execution_report_data(b"tarball", &args, &inputs, b"55"));
+    }
+
+    #[test]
+    fn mock_quotes_parse_back() {
+        let data = report_data(&["10"], &BTreeMap::new(), b"55\n");
+        let quote = TdxQuote::parse(&MockProvider.get_quote(&data).unwrap()).unwrap();
+        assert_eq!(quote.version, TDX_QUOTE_VERSION);
+        assert_eq!(quote.tee_type, TDX_TEE_TYPE);
+        assert_eq!(quote.report_data, data);
+        assert_eq!(quote.mr_td, MockProvider::mr_td());
+        for index in 0..4 {
+            assert_eq!(quote.rtmr[index], MockProvider::rtmr(index));
+        }
+    }
+
+    #[test]
+    fn rejects_quotes_that_are_not_tdx_v4() {
+        let quote = MockProvider.get_quote(&[0; REPORT_DATA_LEN]).unwrap();
+        assert!(TdxQuote::parse(&quote[..QUOTE_HEADER_LEN + TD_REPORT_BODY_LEN - 1]).is_err());
+
+        let mut other_version = quote.clone();
+        other_version[0..2].copy_from_slice(&3u16.to_le_bytes());
+        assert!(TdxQuote::parse(&other_version).is_err());
+
+        let mut sgx = quote;
+        sgx[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(TdxQuote::parse(&sgx).is_err());
+    }
+}
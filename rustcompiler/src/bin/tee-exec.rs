@@ -0,0 +1,190 @@
+// Target runner used by the compiler service
+//
+// Usage: tee-exec [--cpu SECS] [--memory BYTES] [--nproc N] [--cgroup DIR] [--stats FILE]
+//                 [--sandbox [--share-net] [--scratch BYTES] [--ro-bind SRC[:DST]]... [--bind SRC[:DST]]...]
+//                 -- PROGRAM [ARGS...]
+//
+// Applies the rlimits and execs the program, so the limits cover the user
+// binary and its children but not cargo. With --cgroup the memory and process
+// limits are those of a cgroup created under DIR for the run instead of
+// rlimits, and tee-exec waits outside of it to record its events. With
+// --sandbox the program runs in its own namespaces with only the bound paths
+// visible (see sandbox.rs). With --stats the program's exit code or signal
+// and peak RSS are written to FILE as JSON once it exits; tee-exec then waits
+// for it instead of exec'ing.
+
+use rust_tee_compiler::limits::{self, RunCgroup};
+use rust_tee_compiler::sandbox::{self, BindMount, ExitStats, SandboxSpec};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, ExitStatus};
+
+const USAGE: &str = "Usage: tee-exec [--cpu SECS] [--memory BYTES] [--nproc N] [--cgroup DIR] [--stats FILE] \
+[--sandbox [--share-net] [--scratch BYTES] [--ro-bind SRC[:DST]]... [--bind SRC[:DST]]...] -- PROGRAM [ARGS...]";
+
+// Rlimits to apply right before the program starts
+#[derive(Clone, Copy)]
+struct Rlimits {
+    cpu_secs: Option<u64>,
+    memory_bytes: Option<u64>,
+    max_processes: Option<u64>,
+}
+
+fn run() -> Result<u8, String> {
+    let mut cpu_secs = None;
+    let mut memory_bytes = None;
+    let mut max_processes = None;
+    let mut cgroup_parent = None;
+    let mut sandboxed = false;
+    let mut stats_path = None;
+    let mut spec = SandboxSpec {
+        mounts: Vec::new(),
+        allow_network: false,
+        workdir: PathBuf::from(sandbox::WORKDIR),
+        scratch_bytes: 64 * 1024 * 1024,
+    };
+
+    let mut cli = std::env::args().skip(1);
+    loop {
+        let flag = cli.next().ok_or_else(|| USAGE.to_string())?;
+        match flag.as_str() {
+            "--" => break,
+            "--sandbox" => {
+                sandboxed = true;
+                continue;
+            }
+            "--share-net" => {
+                spec.allow_network = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let value = cli.next().ok_or_else(|| format!("Missing value for {}\n{}", flag, USAGE))?;
+        let number = || value.parse::<u64>().map_err(|e| format!("Invalid value for {}: {}", flag, e));
+        match flag.as_str() {
+            "--cpu" => cpu_secs = Some(number()?),
+            "--memory" => memory_bytes = Some(number()?),
+            "--nproc" => max_processes = Some(number()?),
+            "--scratch" => spec.scratch_bytes = number()?,
+            "--cgroup" => cgroup_parent = Some(PathBuf::from(&value)),
+            "--stats" => stats_path = Some(PathBuf::from(&value)),
+            "--ro-bind" => spec.mounts.push(BindMount::parse(&value, false)),
+            "--bind" => spec.mounts.push(BindMount::parse(&value, true)),
+            other => return Err(format!("Unknown argument: {}\n{}", other, USAGE)),
+        }
+    }
+
+    let program = cli.next().ok_or_else(|| format!("Missing program\n{}", USAGE))?;
+    let args: Vec<String> = cli.collect();
+    let spec = sandboxed.then_some(spec);
+
+    // Opened before entering any namespace, so the path is the host's
+    let stats = stats_path
+        .as_ref()
+        .map(|path| std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e)))
+        .transpose()?;
+
+    let Some(cgroup_parent) = cgroup_parent else {
+        let rlimits = Rlimits { cpu_secs, memory_bytes, max_processes };
+        return launch(&program, &args, spec.as_ref(), stats.as_ref(), rlimits);
+    };
+
+    // memory.max and pids.max replace RLIMIT_AS and RLIMIT_NPROC, which would
+    // make allocations and forks fail before the cgroup could see them
+    let cgroup = RunCgroup::create(&cgroup_parent, memory_bytes, max_processes)?;
+    let child = unsafe { libc::fork() };
+    if child < 0 {
+        return Err(format!("Failed to fork: {}", std::io::Error::last_os_error()));
+    }
+    if child == 0 {
+        let rlimits = Rlimits { cpu_secs, memory_bytes: None, max_processes: None };
+        let code = cgroup.enter()
+            .and_then(|_| launch(&program, &args, spec.as_ref(), stats.as_ref(), rlimits))
+            .unwrap_or_else(|e| {
+                eprintln!("tee-exec: {}", e);
+                126
+            });
+        unsafe { libc::_exit(code as i32) };
+    }
+
+    let status = sandbox::wait_for(child);
+    // Missing if the child was killed before the program exited
+    if let Some(path) = &stats_path {
+        if let Ok(mut exit_stats) = ExitStats::read_from(path) {
+            cgroup.record_events(&mut exit_stats);
+            let file = std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            exit_stats.write_to(&file)?;
+        }
+    }
+    drop(cgroup);
+    Ok(exit_like(ExitStatus::from_raw(status?)))
+}
+
+// Starts the program with the rlimits applied, in the sandbox if there is a
+// spec, and returns tee-exec's exit code
+fn launch(
+    program: &str,
+    args: &[String],
+    spec: Option<&SandboxSpec>,
+    stats: Option<&std::fs::File>,
+    rlimits: Rlimits,
+) -> Result<u8, String> {
+    let apply_rlimits = || {
+        limits::apply_rlimits(rlimits.cpu_secs, rlimits.memory_bytes, rlimits.max_processes)
+            .map_err(|e| format!("Failed to apply resource limits: {}", e))
+    };
+
+    if let Some(spec) = spec {
+        let status = sandbox::run_sandboxed(spec, Path::new(program), args, stats, apply_rlimits)?;
+        return Ok(exit_like(status));
+    }
+
+    if let Some(stats) = stats {
+        let child = unsafe { libc::fork() };
+        if child < 0 {
+            return Err(format!("Failed to fork: {}", std::io::Error::last_os_error()));
+        }
+        if child == 0 {
+            let error = apply_rlimits().err().unwrap_or_else(|| {
+                format!("Failed to exec {}: {}", program, Command::new(program).args(args).exec())
+            });
+            eprintln!("tee-exec: {}", error);
+            unsafe { libc::_exit(126) };
+        }
+        let (code, exit_stats) = sandbox::wait_with_stats(child)?;
+        exit_stats.write_to(stats)?;
+        return Ok(code as u8);
+    }
+
+    apply_rlimits()?;
+
+    // exec only returns on failure
+    let error = Command::new(program).args(args).exec();
+    Err(format!("Failed to exec {}: {}", program, error))
+}
+
+// Ends tee-exec the way the sandbox helper ended. A helper killed by a
+// signal, e.g. by the OOM killer, takes tee-exec down with the same signal
+// rather than reporting success.
+fn exit_like(status: ExitStatus) -> u8 {
+    let Some(signal) = status.signal() else {
+        return status.code().unwrap_or(126) as u8;
+    };
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+    // Only reached for signals that don't terminate
+    128 + signal as u8
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("tee-exec: {}", e);
+            ExitCode::from(126)
+        }
+    }
+}
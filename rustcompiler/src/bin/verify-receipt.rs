@@ -0,0 +1,130 @@
+// Offline verifier for /run and /execute responses
+//
+// Usage: verify-receipt --response response.json (--tarball project.tar.gz | --source-sha256 HEX)
+//            [--args "10 20" | --arg VALUE...] [--lockfile Cargo.lock] [--binary target/release/app]
+//            [--stdin FILE] [--env NAME=VALUE]... [--input FILE]...
+//            [--mrtd HEX] [--rtmr INDEX=HEX]... --pubkey-attestation pubkey.json
+//
+// Prints a JSON report with one entry per check and exits non-zero if any
+// check failed or no key attestation was given. Receipts of /execute commit
+// to the source hash of the cached build instead of a tarball.
+
+use rust_tee_compiler::artifacts;
+use rust_tee_compiler::attestation::ExecutedCode;
+use rust_tee_compiler::verify::{self, ExecutionEvidence, ExpectedMeasurements, KeyAttestation, VerifyOptions};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: verify-receipt --response FILE (--tarball FILE | --source-sha256 HEX) [--args ARGS | --arg VALUE...] [--lockfile FILE] [--binary FILE] [--stdin FILE] [--env NAME=VALUE]... [--input FILE]... [--mrtd HEX] [--rtmr INDEX=HEX]... --pubkey-attestation FILE";
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_slice(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+fn run() -> Result<bool, String> {
+    let mut response_path = None;
+    let mut tarball_path = None;
+    let mut source_sha256 = None;
+    let mut args = Vec::new();
+    let mut lockfile_path = None;
+    let mut binary_path = None;
+    let mut stdin_path = None;
+    let mut env = BTreeMap::new();
+    let mut input_paths = Vec::new();
+    let mut measurements = ExpectedMeasurements::default();
+    let mut pubkey_path = None;
+
+    let mut cli = std::env::args().skip(1);
+    while let Some(flag) = cli.next() {
+        let mut value = || cli.next().ok_or_else(|| format!("Missing value for {}\n{}", flag, USAGE));
+        match flag.as_str() {
+            "--response" => response_path = Some(value()?),
+            "--tarball" => tarball_path = Some(value()?),
+            "--source-sha256" => source_sha256 = Some(value()?),
+            // Split on whitespace, the same way the server parses the args field
+            "--args" => args = value()?.split_whitespace().map(|s| s.to_string()).collect(),
+            // One argument each, for args with spaces (run_request or params)
+            "--arg" => args.push(value()?),
+            "--lockfile" => lockfile_path = Some(value()?),
+            "--binary" => binary_path = Some(value()?),
+            "--stdin" => stdin_path = Some(value()?),
+            "--env" => {
+                let pair = value()?;
+                let (name, value) = pair.split_once('=').ok_or_else(|| format!("Expected NAME=VALUE for --env, got {}", pair))?;
+                env.insert(name.to_string(), value.to_string());
+            }
+            // Checked under its file name, as it was uploaded
+            "--input" => input_paths.push(value()?),
+            "--mrtd" => measurements.mr_td = Some(value()?),
+            "--rtmr" => {
+                let pair = value()?;
+                let register = pair.split_once('=')
+                    .and_then(|(index, value)| Some((index.parse::<usize>().ok().filter(|index| *index < 4)?, value)));
+                let (index, value) = register.ok_or_else(|| format!("Expected INDEX=HEX with INDEX 0-3 for --rtmr, got {}", pair))?;
+                measurements.rtmr[index] = Some(value.to_string());
+            }
+            "--pubkey-attestation" => pubkey_path = Some(value()?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(true);
+            }
+            other => return Err(format!("Unknown argument: {}\n{}", other, USAGE)),
+        }
+    }
+
+    let response_path = response_path.ok_or_else(|| format!("Missing --response\n{}", USAGE))?;
+    let evidence: ExecutionEvidence = read_json(&response_path)?;
+    let code = match (tarball_path, source_sha256) {
+        (Some(path), None) => {
+            let tarball = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            ExecutedCode::Tarball(Sha256::digest(&tarball).into())
+        }
+        (None, Some(hash)) => ExecutedCode::Source(artifacts::parse_digest(&hash)?),
+        _ => return Err(format!("Expected one of --tarball or --source-sha256\n{}", USAGE)),
+    };
+    let lockfile = lockfile_path
+        .map(|path| std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e)))
+        .transpose()?;
+    let binary = binary_path
+        .map(|path| std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e)))
+        .transpose()?;
+    let stdin = stdin_path
+        .map(|path| std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e)))
+        .transpose()?;
+    let input_files = input_paths.iter()
+        .map(|path| {
+            let name = Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            std::fs::read(path).map(|contents| (name, contents)).map_err(|e| format!("Failed to read {}: {}", path, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let key_attestation: Option<KeyAttestation> = pubkey_path.as_deref().map(read_json).transpose()?;
+
+    let report = verify::verify_execution(&evidence, &VerifyOptions {
+        code,
+        args: &args,
+        lockfile: lockfile.as_deref(),
+        binary: binary.as_deref(),
+        stdin: stdin.as_deref(),
+        env: &env,
+        input_files: &input_files,
+        expected_measurements: &measurements,
+        key_attestation: key_attestation.as_ref(),
+    });
+
+    println!("{}", serde_json::to_string_pretty(&report).map_err(|e| format!("Failed to serialize report: {}", e))?);
+    Ok(report.passed)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// Cargo target directories shared between projects
+//
+// Cargo already stores every compiled crate under a hash of its package id,
+// version, features, profile and rustc, so one target directory per toolchain
+// and profile acts as a content-addressed cache of dependency artifacts: a
+// second project using num-bigint 0.4 with the same features reuses the rlib
+// instead of compiling it again.
+//
+// Builds never write to the cache directly. Each build compiles in a private
+// target directory seeded with a copy of the cache, and afterwards only the
+// artifacts of registry crates are copied back, so one project's own crates
+// and binaries never become visible to another. Entries already in the cache
+// are not replaced. Seeding and publishing hold an exclusive lock on the
+// directory; builds of different toolchains or profiles do not contend.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = ".walrustee.lock";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCache {
+    pub enabled: bool,
+    pub dir: PathBuf,
+}
+
+// Exclusive hold on a shared target directory, released on drop
+pub struct TargetLock {
+    _file: File,
+}
+
+impl DependencyCache {
+    // DEPENDENCY_CACHE=off gives every project its own target/ again;
+    // DEPENDENCY_CACHE_DIR moves the cache (default ./dep-cache)
+    pub fn from_env() -> Self {
+        Self {
+            enabled: std::env::var("DEPENDENCY_CACHE").map(|v| v != "off").unwrap_or(true),
+            dir: PathBuf::from(std::env::var("DEPENDENCY_CACHE_DIR").unwrap_or_else(|_| "./dep-cache".to_string())),
+        }
+    }
+
+    // Target directory for builds with this toolchain and profile, e.g.
+    // dep-cache/release-3f2a9c0b1d4e5f60
+    pub fn target_dir(&self, toolchain: &str, profile: &str) -> PathBuf {
+        let toolchain_hash = hex::encode(&Sha256::digest(toolchain.as_bytes())[..8]);
+        self.dir.join(format!("{}-{}", profile, toolchain_hash))
+    }
+
+    // Creates the directory if needed and blocks until it is free
+    pub fn lock(target_dir: &Path) -> io::Result<TargetLock> {
+        fs::create_dir_all(target_dir)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(target_dir.join(LOCK_FILE))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(TargetLock { _file: file })
+    }
+
+    // Copies the cached artifacts into a build's empty target directory
+    pub fn seed(cache_dir: &Path, target_dir: &Path) -> io::Result<()> {
+        copy_tree(cache_dir, target_dir, &|_| true)
+    }
+
+    // Copies the artifacts of `shared` crates from a build's target directory
+    // into the cache
+    pub fn publish(target_dir: &Path, cache_dir: &Path, shared: &SharedCrates) -> io::Result<()> {
+        copy_tree(target_dir, cache_dir, &|path| shared.owns(path))
+    }
+}
+
+// Crate names whose artifacts may be shared: those of registry packages, and
+// none that a workspace, path or git package of the build also uses
+#[derive(Debug, Clone, Default)]
+pub struct SharedCrates {
+    registry: HashSet<String>,
+    local: HashSet<String>,
+}
+
+impl SharedCrates {
+    // Both sets take package names and crate names alike; they are compared
+    // with `-` and `_` treated as the same
+    pub fn new<'a>(registry: impl IntoIterator<Item = &'a str>, local: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            registry: registry.into_iter().map(normalize).collect(),
+            local: local.into_iter().map(normalize).collect(),
+        }
+    }
+
+    // Whether a path in a target directory, relative to it, is an artifact
+    // of a shared crate: `<profile>/deps/lib<crate>-<hash>.rlib` and the like,
+    // or `<profile>/.fingerprint/<package>-<hash>/...`. Binaries, build
+    // script output and incremental data are never shared.
+    fn owns(&self, path: &Path) -> bool {
+        let parts: Vec<&str> = path.iter().filter_map(|part| part.to_str()).collect();
+        let name = match parts.as_slice() {
+            [_, "deps", file] => *file,
+            [_, ".fingerprint", dir, ..] => *dir,
+            _ => return false,
+        };
+        let stem = name.split('.').next().unwrap_or(name);
+        let Some((crate_name, _hash)) = stem.rsplit_once('-') else {
+            return false;
+        };
+        let candidates = [Some(crate_name), crate_name.strip_prefix("lib")];
+        let candidates = candidates.iter().flatten().map(|name| normalize(name));
+        let mut owned = false;
+        for candidate in candidates {
+            if self.local.contains(&candidate) {
+                return false;
+            }
+            owned |= self.registry.contains(&candidate);
+        }
+        owned
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+// Copies the files under `from` that `include` accepts (by path relative to
+// `from`) to the same place under `to`, keeping modification times and
+// leaving files that already exist there alone. Symlinks are skipped.
+fn copy_tree(from: &Path, to: &Path, include: &dyn Fn(&Path) -> bool) -> io::Result<()> {
+    fn walk(root: &Path, dir: &Path, to: &Path, include: &dyn Fn(&Path) -> bool) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                walk(root, &path, to, include)?;
+            } else if file_type.is_file() && relative != Path::new(LOCK_FILE) && include(relative) {
+                let destination = to.join(relative);
+                if destination.exists() {
+                    continue;
+                }
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&path, &destination)?;
+                File::options().write(true).open(&destination)?.set_modified(entry.metadata()?.modified()?)?;
+            }
+        }
+        Ok(())
+    }
+    walk(from, from, to, include)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared() -> SharedCrates {
+        SharedCrates::new(["itoa", "num-bigint", "libc"], ["app", "c"])
+    }
+
+    #[test]
+    fn shares_only_registry_crate_artifacts() {
+        let shared = shared();
+        for path in [
+            "release/deps/libitoa-0123abcd.rlib",
+            "release/deps/libitoa-0123abcd.rmeta",
+            "release/deps/itoa-0123abcd.d",
+            "release/deps/libnum_bigint-0123abcd.rlib",
+            "release/.fingerprint/num-bigint-0123abcd/lib-num_bigint",
+        ] {
+            assert!(shared.owns(Path::new(path)), "{}", path);
+        }
+        for path in [
+            // The project's own crate and binaries
+            "release/deps/app-0123abcd",
+            "release/deps/libapp-0123abcd.rlib",
+            "release/.fingerprint/app-0123abcd/bin-app",
+            "release/app",
+            "release/build/itoa-0123abcd/output",
+            "release/incremental/itoa-0123abcd/s-1",
+            // liblibc or libc? Either way a local crate may own it
+            "release/deps/libc-0123abcd.d",
+            "release/deps/unknown-0123abcd.d",
+        ] {
+            assert!(!shared.owns(Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn publishes_without_replacing_cached_entries() {
+        let dir = std::env::temp_dir().join(format!("depcache-test-{}", std::process::id()));
+        let (target, cache) = (dir.join("target"), dir.join("cache"));
+        fs::create_dir_all(target.join("release/deps")).unwrap();
+        fs::create_dir_all(cache.join("release/deps")).unwrap();
+        fs::write(target.join("release/deps/libitoa-1.rlib"), "new").unwrap();
+        fs::write(target.join("release/deps/libitoa-2.rlib"), "built").unwrap();
+        fs::write(target.join("release/deps/libapp-1.rlib"), "private").unwrap();
+        fs::write(cache.join("release/deps/libitoa-1.rlib"), "cached").unwrap();
+
+        DependencyCache::publish(&target, &cache, &shared()).unwrap();
+        assert_eq!(fs::read_to_string(cache.join("release/deps/libitoa-1.rlib")).unwrap(), "cached");
+        assert_eq!(fs::read_to_string(cache.join("release/deps/libitoa-2.rlib")).unwrap(), "built");
+        assert!(!cache.join("release/deps/libapp-1.rlib").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
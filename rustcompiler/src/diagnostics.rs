@@ -0,0 +1,224 @@
+// Compiler diagnostics from cargo's `--message-format=json` output
+//
+// Every `compiler-message` line carries one rustc diagnostic as JSON. They
+// are turned into a flatter shape for API clients: the primary location,
+// the spans with their labels, the attached notes and the replacements
+// rustc suggests, e.g. for an editor to show inline.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    Help,
+    FailureNote,
+    InternalCompilerError,
+}
+
+impl Level {
+    // rustc's name for the level
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "error" => Some(Level::Error),
+            "warning" => Some(Level::Warning),
+            "note" => Some(Level::Note),
+            "help" => Some(Level::Help),
+            "failure-note" => Some(Level::FailureNote),
+            "error: internal compiler error" => Some(Level::InternalCompilerError),
+            _ => None,
+        }
+    }
+
+    pub fn is_error(self) -> bool {
+        matches!(self, Level::Error | Level::InternalCompilerError)
+    }
+}
+
+// A source range; lines and columns are 1-based, the end column exclusive
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Span {
+    pub file: String,
+    pub line_start: u32,
+    pub line_end: u32,
+    pub column_start: u32,
+    pub column_end: u32,
+    // The spot the diagnostic is about, rather than related code
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+// A fix rustc proposes: replace the span with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+    // MachineApplicable, MaybeIncorrect, HasPlaceholders or Unspecified
+    pub applicability: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Diagnostic {
+    pub level: Level,
+    // Error or lint code, e.g. E0308 or unused_variables
+    pub code: Option<String>,
+    pub message: String,
+    // File of the primary span
+    pub file: Option<String>,
+    pub spans: Vec<Span>,
+    // Notes and help without a replacement, e.g. "note: expected due to this"
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+    // The diagnostic as rustc prints it
+    pub rendered: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<RustcDiagnostic>,
+}
+
+#[derive(serde::Deserialize)]
+struct RustcDiagnostic {
+    message: String,
+    code: Option<RustcCode>,
+    level: String,
+    #[serde(default)]
+    spans: Vec<RustcSpan>,
+    #[serde(default)]
+    children: Vec<RustcDiagnostic>,
+    rendered: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct RustcCode {
+    code: String,
+}
+
+#[derive(serde::Deserialize)]
+struct RustcSpan {
+    file_name: String,
+    line_start: u32,
+    line_end: u32,
+    column_start: u32,
+    column_end: u32,
+    is_primary: bool,
+    label: Option<String>,
+    suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
+}
+
+impl RustcSpan {
+    fn span(&self) -> Span {
+        Span {
+            file: self.file_name.clone(),
+            line_start: self.line_start,
+            line_end: self.line_end,
+            column_start: self.column_start,
+            column_end: self.column_end,
+            is_primary: self.is_primary,
+            label: self.label.clone(),
+        }
+    }
+}
+
+fn compiler_message(line: &str) -> Option<RustcDiagnostic> {
+    let message = serde_json::from_str::<CargoMessage>(line).ok()?;
+    (message.reason == "compiler-message").then_some(message.message).flatten()
+}
+
+// The diagnostic of one line of cargo output, if it holds one
+pub fn parse_line(line: &str) -> Option<Diagnostic> {
+    let diagnostic = compiler_message(line)?;
+    let level = Level::parse(&diagnostic.level)?;
+
+    let mut notes = Vec::new();
+    let mut suggestions = Vec::new();
+    for child in &diagnostic.children {
+        let replacements: Vec<&RustcSpan> = child.spans.iter()
+            .filter(|span| span.suggested_replacement.is_some())
+            .collect();
+        if replacements.is_empty() {
+            notes.push(format!("{}: {}", child.level, child.message));
+        }
+        for span in replacements {
+            suggestions.push(Suggestion {
+                message: child.message.clone(),
+                span: span.span(),
+                replacement: span.suggested_replacement.clone().unwrap_or_default(),
+                applicability: span.suggestion_applicability.clone(),
+            });
+        }
+    }
+
+    Some(Diagnostic {
+        level,
+        code: diagnostic.code.map(|code| code.code),
+        message: diagnostic.message,
+        file: diagnostic.spans.iter().find(|span| span.is_primary).map(|span| span.file_name.clone()),
+        spans: diagnostic.spans.iter().map(RustcSpan::span).collect(),
+        notes,
+        suggestions,
+        rendered: diagnostic.rendered.unwrap_or_default(),
+    })
+}
+
+// Every diagnostic in cargo's stdout, in the order rustc emitted them
+pub fn parse(cargo_stdout: &str) -> Vec<Diagnostic> {
+    cargo_stdout.lines().filter_map(parse_line).collect()
+}
+
+// What rustc would have printed for a line of cargo output, so build logs
+// read the same as with human-readable messages
+pub fn rendered(line: &str) -> Option<String> {
+    compiler_message(line)?.rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERROR: &str = r#"{"reason":"compiler-message","message":{"message":"mismatched types","code":{"code":"E0308","explanation":null},"level":"error","spans":[{"file_name":"src/main.rs","line_start":5,"line_end":5,"column_start":11,"column_end":12,"is_primary":true,"label":"expected `&Vec<i32>`, found `Vec<{integer}>`","suggested_replacement":null,"suggestion_applicability":null}],"children":[{"message":"function defined here","code":null,"level":"note","spans":[],"children":[],"rendered":null},{"message":"consider borrowing here","code":null,"level":"help","spans":[{"file_name":"src/main.rs","line_start":5,"line_end":5,"column_start":11,"column_end":11,"is_primary":true,"label":null,"suggested_replacement":"&","suggestion_applicability":"MachineApplicable"}],"children":[],"rendered":null}],"rendered":"error[E0308]: mismatched types\n"}}"#;
+    const WARNING: &str = r#"{"reason":"compiler-message","message":{"message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"src/main.rs","line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true,"label":null,"suggested_replacement":null,"suggestion_applicability":null}],"children":[],"rendered":"warning: unused variable: `x`\n"}}"#;
+    const ARTIFACT: &str = r#"{"reason":"compiler-artifact","package_id":"app 0.1.0","target":{"name":"app","kind":["bin"]}}"#;
+
+    #[test]
+    fn parses_errors_with_notes_and_suggestions() {
+        let diagnostic = parse_line(ERROR).unwrap();
+        assert_eq!(diagnostic.level, Level::Error);
+        assert_eq!(diagnostic.code.as_deref(), Some("E0308"));
+        assert_eq!(diagnostic.file.as_deref(), Some("src/main.rs"));
+        assert_eq!(diagnostic.spans.len(), 1);
+        assert_eq!(diagnostic.notes, ["note: function defined here"]);
+        assert_eq!(diagnostic.suggestions.len(), 1);
+        assert_eq!(diagnostic.suggestions[0].replacement, "&");
+        assert_eq!(diagnostic.suggestions[0].applicability.as_deref(), Some("MachineApplicable"));
+        assert_eq!(diagnostic.rendered, "error[E0308]: mismatched types\n");
+    }
+
+    #[test]
+    fn warnings_only_output_has_no_errors() {
+        let output = [ARTIFACT, WARNING, "not json", ARTIFACT].join("\n");
+        let diagnostics = parse(&output);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Warning);
+        assert!(!diagnostics.iter().any(|diagnostic| diagnostic.level.is_error()));
+    }
+
+    #[test]
+    fn renders_only_compiler_messages() {
+        assert_eq!(rendered(WARNING).as_deref(), Some("warning: unused variable: `x`\n"));
+        assert_eq!(rendered(ARTIFACT), None);
+        assert_eq!(rendered("   Compiling app v0.1.0"), None);
+    }
+
+    #[test]
+    fn levels_parse_from_rustc_names() {
+        assert_eq!(Level::parse("error: internal compiler error"), Some(Level::InternalCompilerError));
+        assert!(Level::InternalCompilerError.is_error());
+        assert!(!Level::parse("failure-note").unwrap().is_error());
+        assert_eq!(Level::parse("fatal"), None);
+    }
+}
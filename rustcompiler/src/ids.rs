@@ -0,0 +1,104 @@
+// Validated user and project identifiers
+//
+// Both end up as directory names under ./projects, so they are restricted to
+// a short, path-safe charset before they get anywhere near the filesystem.
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const MAX_ID_LEN: usize = 64;
+
+fn validate(kind: &str, value: &str) -> Result<(), String> {
+    if value.is_empty() || value.len() > MAX_ID_LEN {
+        return Err(format!("Invalid {}: must be 1 to {} characters long", kind, MAX_ID_LEN));
+    }
+    if let Some(c) = value.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_')) {
+        return Err(format!("Invalid {}: character {:?} is not allowed, use only A-Z, a-z, 0-9, '-' and '_'", kind, c));
+    }
+    // Keeps ids from being read as flags when passed to commands
+    if value.starts_with('-') {
+        return Err(format!("Invalid {}: must not start with '-'", kind));
+    }
+    Ok(())
+}
+
+macro_rules! id_type {
+    ($name:ident, $kind:literal) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                validate($kind, value)?;
+                Ok(Self(value.to_string()))
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                validate($kind, &value)?;
+                Ok(Self(value))
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+id_type!(UserId, "user_id");
+id_type!(ProjectId, "project_id");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_allowed_characters() {
+        for id in ["u1", "alice", "Project_2", "a-b_c", "_", "0", &"x".repeat(MAX_ID_LEN)] {
+            assert_eq!(id.parse::<UserId>().map(|id| id.to_string()), Ok(id.to_string()), "{}", id);
+        }
+        assert_eq!("fib-prime".parse::<ProjectId>().unwrap().as_str(), "fib-prime");
+    }
+
+    #[test]
+    fn rejects_paths_and_other_characters() {
+        for id in ["..", ".", "../etc", "a/b", "/", "a\\b", "a.b", "a b", "a\0b", "~", "é", "-rf"] {
+            assert!(id.parse::<UserId>().is_err(), "{:?}", id);
+            assert!(id.parse::<ProjectId>().is_err(), "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_overlong_ids() {
+        assert!("".parse::<UserId>().is_err());
+        assert!("x".repeat(MAX_ID_LEN + 1).parse::<ProjectId>().is_err());
+    }
+
+    #[test]
+    fn deserializing_validates_too() {
+        assert_eq!(serde_json::from_str::<UserId>("\"alice\"").unwrap().as_str(), "alice");
+        assert!(serde_json::from_str::<UserId>("\"../alice\"").is_err());
+        assert_eq!(serde_json::to_string(&"bob".parse::<ProjectId>().unwrap()).unwrap(), "\"bob\"");
+    }
+}
@@ -0,0 +1,516 @@
+// Asynchronous execution jobs
+//
+// Every project build or execution runs as a job: it is queued until a worker
+// slot is free, moves through building and running, and ends up
+// succeeded, failed or cancelled. Logs are kept per job so clients can poll
+// progress, and are also broadcast live to streaming subscribers. The whole
+// process group can be killed on cancellation.
+
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command as TokioCommand;
+use tokio::sync::{broadcast, watch, Notify, Semaphore};
+use tokio::time::{sleep_until, Instant};
+
+use crate::types::{CompilerApiError, JobError, JobResponse};
+use rust_tee_compiler::diagnostics;
+use rust_tee_compiler::limits::{LimitKind, ResourceLimits};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Building,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+// Part of a job a process belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Build,
+    Run,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    // cargo output before the program starts
+    Build,
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogStream::Build => "build",
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LogLine {
+    pub stream: LogStream,
+    pub text: String,
+}
+
+// Final event of a job stream
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ExitEvent {
+    pub state: JobState,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    Log(LogLine),
+    Finished(ExitEvent),
+}
+
+// Events buffered per subscriber before it starts lagging
+const EVENT_CAPACITY: usize = 1024;
+// Size of one read from a process's stdout or stderr
+const READ_CHUNK: usize = 8192;
+
+pub type JobResult = Result<JobResponse, JobError>;
+
+struct JobInner {
+    state: JobState,
+    logs: Vec<LogLine>,
+    result: Option<JobResult>,
+    pid: Option<u32>,
+    exit_status: Option<ExitStatus>,
+    cancelled: bool,
+    finished_at: Option<Instant>,
+}
+
+impl JobInner {
+    fn exit_event(&self) -> Option<ExitEvent> {
+        if !self.state.is_finished() {
+            return None;
+        }
+        Some(ExitEvent {
+            state: self.state,
+            exit_code: self.exit_status.and_then(|status| status.code()),
+            signal: self.exit_status.and_then(|status| status.signal()),
+            error: match &self.result {
+                Some(Err(failure)) => Some(failure.error.message().to_string()),
+                _ => None,
+            },
+        })
+    }
+}
+
+pub struct Job {
+    pub id: String,
+    pub user_id: String,
+    pub project_id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    inner: Mutex<JobInner>,
+    finished: watch::Sender<bool>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+// Output of a process run under a job
+pub struct ProcessOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    // Limit enforced by the supervisor, if the process was killed for one
+    pub limit_exceeded: Option<LimitKind>,
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn logs(&self) -> Vec<LogLine> {
+        self.inner.lock().unwrap().logs.clone()
+    }
+
+    // Lines logged to one stream so far, joined back into text
+    pub fn log_text(&self, stream: LogStream) -> String {
+        let inner = self.inner.lock().unwrap();
+        let lines: Vec<&str> = inner.logs.iter()
+            .filter(|line| line.stream == stream)
+            .map(|line| line.text.as_str())
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn result(&self) -> Option<JobResult> {
+        self.inner.lock().unwrap().result.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().unwrap().cancelled
+    }
+
+    pub fn set_state(&self, state: JobState) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.state.is_finished() {
+            inner.state = state;
+        }
+    }
+
+    // Logs so far, the exit event if the job is done, and a receiver for
+    // everything that happens afterwards. Taken under one lock so nothing is
+    // missed or delivered twice.
+    pub fn subscribe(&self) -> (Vec<LogLine>, Option<ExitEvent>, broadcast::Receiver<JobEvent>) {
+        let inner = self.inner.lock().unwrap();
+        (inner.logs.clone(), inner.exit_event(), self.events.subscribe())
+    }
+
+    fn push_log(&self, stream: LogStream, text: String) {
+        let line = LogLine { stream, text };
+        let mut inner = self.inner.lock().unwrap();
+        // No subscribers is fine, the line is still kept for polling
+        let _ = self.events.send(JobEvent::Log(line.clone()));
+        inner.logs.push(line);
+    }
+
+    fn finish(&self, result: JobResult) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.state = if inner.cancelled {
+                JobState::Cancelled
+            } else if result.is_ok() {
+                JobState::Succeeded
+            } else {
+                JobState::Failed
+            };
+            inner.result = Some(if inner.cancelled {
+                // Keep what the job produced before it was killed
+                let report = result.err().map(|failure| failure.report).unwrap_or_default();
+                let error = CompilerApiError::Cancelled { message: "Job was cancelled".to_string() };
+                Err(JobError { error, report })
+            } else {
+                result
+            });
+            inner.pid = None;
+            inner.finished_at = Some(Instant::now());
+            if let Some(event) = inner.exit_event() {
+                let _ = self.events.send(JobEvent::Finished(event));
+            }
+        }
+        let _ = self.finished.send(true);
+    }
+
+    // Marks the job cancelled and kills its process group if one is running.
+    // Returns false if the job had already finished.
+    pub fn cancel(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state.is_finished() {
+            return false;
+        }
+        inner.cancelled = true;
+        if let Some(pid) = inner.pid {
+            println!("Killing process group {} of job {}", pid, self.id);
+            // The child was started as its own process group leader, so this
+            // also reaches cargo and the compiled program
+            unsafe {
+                libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+        true
+    }
+
+    pub async fn wait(&self) {
+        let mut finished = self.finished.subscribe();
+        let _ = finished.wait_for(|done| *done).await;
+    }
+
+    // Runs the command in its own process group, recording its output as job
+    // logs, and kills the group once the deadline passes. During the build,
+    // stderr is logged as build output and stdout is only captured (cargo
+    // writes its JSON messages there). During the run, stdout and stderr are
+    // logged as such. Output beyond the cap of the phase kills the process
+    // group.
+    pub async fn run_process(
+        &self,
+        mut command: TokioCommand,
+        phase: Phase,
+        deadline: Instant,
+        limits: &ResourceLimits,
+        stdin: Option<&[u8]>,
+    ) -> Result<ProcessOutput, String> {
+        command
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true);
+
+        let mut child = {
+            // Hold the lock across spawn so a concurrent cancel can't miss the pid
+            let mut inner = self.inner.lock().unwrap();
+            if inner.cancelled {
+                return Err("Job was cancelled".to_string());
+            }
+            let child = command.spawn().map_err(|e| format!("Failed to spawn process: {}", e))?;
+            inner.pid = child.id();
+            child
+        };
+        let pid = child.id();
+        self.set_state(match phase {
+            Phase::Build => JobState::Building,
+            Phase::Run => JobState::Running,
+        });
+
+        let stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
+        let stderr = child.stderr.take().ok_or("Failed to capture stderr")?;
+        let stdin_pipe = child.stdin.take();
+
+        let exceeded: Mutex<Option<LimitKind>> = Mutex::new(None);
+        let kill = |limit: LimitKind| {
+            let mut exceeded = exceeded.lock().unwrap();
+            if exceeded.is_none() {
+                println!("Job {} hit limit {:?}, killing process group", self.id, limit);
+                *exceeded = Some(limit);
+                if let Some(pid) = pid {
+                    unsafe {
+                        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+                    }
+                }
+            }
+        };
+
+        let output_bytes = AtomicU64::new(0);
+        let (max_output_bytes, output_limit) = match phase {
+            Phase::Build => (limits.max_build_output_bytes, LimitKind::BuildOutputSize),
+            Phase::Run => (limits.max_output_bytes, LimitKind::OutputSize),
+        };
+        // Counts output against the cap of the phase; returns false once it
+        // is exceeded
+        let record_output = |bytes: usize| {
+            let total = output_bytes.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+            if total > max_output_bytes {
+                kill(output_limit);
+                return false;
+            }
+            true
+        };
+
+        let exited = Notify::new();
+
+        // Closed once written; the program may exit without reading it all
+        let stdin_writer = async {
+            if let (Some(mut pipe), Some(data)) = (stdin_pipe, stdin) {
+                let _ = pipe.write_all(data).await;
+            }
+        };
+
+        let stdout_reader = read_output(stdout, &record_output, |line| match phase {
+            Phase::Run => self.push_log(LogStream::Stdout, line),
+            // cargo reports diagnostics as JSON; log them as rustc prints them
+            Phase::Build => {
+                for rendered in diagnostics::rendered(&line).iter().flat_map(|text| text.lines()) {
+                    self.push_log(LogStream::Build, rendered.to_string());
+                }
+            }
+        });
+
+        let stderr_stream = match phase {
+            Phase::Build => LogStream::Build,
+            Phase::Run => LogStream::Stderr,
+        };
+        let stderr_reader = read_output(stderr, &record_output, |line| self.push_log(stderr_stream, line));
+
+        let supervisor = async {
+            tokio::select! {
+                _ = sleep_until(deadline) => kill(match phase {
+                    Phase::Build => LimitKind::BuildTimeout,
+                    Phase::Run => LimitKind::RunTimeout,
+                }),
+                _ = exited.notified() => {}
+            }
+        };
+
+        let wait = async {
+            let status = child.wait().await;
+            exited.notify_one();
+            status
+        };
+
+        let (stdout, stderr, status, _, _) = tokio::join!(stdout_reader, stderr_reader, wait, supervisor, stdin_writer);
+        let status = status.map_err(|e| format!("Failed to wait for process: {}", e))?;
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.pid = None;
+            inner.exit_status = Some(status);
+        }
+
+        let limit_exceeded = exceeded.into_inner().unwrap();
+        Ok(ProcessOutput { status, stdout, stderr, limit_exceeded })
+    }
+}
+
+// Reads a pipe to the end in fixed-size chunks and hands each line to
+// `on_line`, without its line ending. Every chunk is counted with `record`
+// before it is kept, and output that is not UTF-8 is decoded lossily instead
+// of ending the read, which would leave the process blocked on a full pipe.
+// Returns the output exactly as read, carriage returns and a missing final
+// newline included, since receipts and quotes commit to it; only bytes that
+// are not UTF-8 are replaced.
+async fn read_output(
+    mut pipe: impl AsyncRead + Unpin,
+    record: impl Fn(usize) -> bool,
+    mut on_line: impl FnMut(String),
+) -> String {
+    let mut collected = Vec::new();
+    let mut chunk = Vec::with_capacity(READ_CHUNK);
+    let mut pending: Vec<u8> = Vec::new();
+    let mut emit = |line: &[u8]| on_line(String::from_utf8_lossy(line).into_owned());
+    loop {
+        chunk.clear();
+        match pipe.read_buf(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !record(chunk.len()) {
+            break;
+        }
+        collected.extend_from_slice(&chunk);
+        let mut rest = chunk.as_slice();
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            pending.extend_from_slice(&rest[..end]);
+            emit(pending.strip_suffix(b"\r").unwrap_or(&pending));
+            pending.clear();
+            rest = &rest[end + 1..];
+        }
+        pending.extend_from_slice(rest);
+    }
+    if !pending.is_empty() {
+        emit(&pending);
+    }
+    String::from_utf8_lossy(&collected).into_owned()
+}
+
+pub struct JobManager {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    slots: Arc<Semaphore>,
+    retention: Duration,
+}
+
+impl JobManager {
+    pub fn new(max_concurrent: usize, retention: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            slots: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            retention,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    // Registers a job and runs `work` once a worker slot is free
+    pub fn submit<F, Fut>(&self, user_id: &str, project_id: &str, work: F) -> Arc<Job>
+    where
+        F: FnOnce(Arc<Job>) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = JobResult> + Send + 'static,
+    {
+        let (finished, _) = watch::channel(false);
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let job = Arc::new(Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            project_id: project_id.to_string(),
+            created_at: chrono::Utc::now(),
+            inner: Mutex::new(JobInner {
+                state: JobState::Queued,
+                logs: Vec::new(),
+                result: None,
+                pid: None,
+                exit_status: None,
+                cancelled: false,
+                finished_at: None,
+            }),
+            finished,
+            events,
+        });
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            // Drop finished jobs that have been around longer than the retention period
+            jobs.retain(|_, job| match job.inner.lock().unwrap().finished_at {
+                Some(finished_at) => finished_at.elapsed() < self.retention,
+                None => true,
+            });
+            jobs.insert(job.id.clone(), job.clone());
+        }
+        println!("Job {} queued for user: {}, project: {}", job.id, user_id, project_id);
+
+        let slots = self.slots.clone();
+        let worker_job = job.clone();
+        tokio::spawn(async move {
+            let _permit = match slots.acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => {
+                    worker_job.finish(Err(CompilerApiError::InternalError { message: "Job queue closed".to_string() }.into()));
+                    return;
+                }
+            };
+            if worker_job.is_cancelled() {
+                worker_job.finish(Err(CompilerApiError::Cancelled { message: "Job was cancelled".to_string() }.into()));
+                return;
+            }
+
+            worker_job.set_state(JobState::Building);
+            let result = work(worker_job.clone()).await;
+            worker_job.finish(result);
+            println!("Job {} finished: {:?}", worker_job.id, worker_job.state());
+        });
+
+        job
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(output: &[u8], cap: usize) -> (String, Vec<String>) {
+        let total = AtomicU64::new(0);
+        let mut lines = Vec::new();
+        let record = |bytes: usize| total.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64 <= cap as u64;
+        let collected = read_output(output, record, |line| lines.push(line)).await;
+        (collected, lines)
+    }
+
+    #[tokio::test]
+    async fn splits_lines_and_keeps_reading_past_invalid_utf8() {
+        let (collected, lines) = read(b"one\r\n\xff\xfe two\nthree", 1024).await;
+        assert_eq!(lines, ["one", "\u{fffd}\u{fffd} two", "three"]);
+        // Kept as the program wrote it, for the receipt
+        assert_eq!(collected, "one\r\n\u{fffd}\u{fffd} two\nthree");
+    }
+
+    #[tokio::test]
+    async fn stops_before_keeping_output_over_the_cap() {
+        let output = vec![b'x'; READ_CHUNK * 4];
+        let (collected, lines) = read(&output, READ_CHUNK).await;
+        assert!(collected.len() <= READ_CHUNK);
+        assert!(lines.len() <= 1);
+    }
+}
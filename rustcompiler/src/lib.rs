@@ -0,0 +1,18 @@
+// Shared between the compiler service and the offline verifier
+pub mod archive;
+pub mod artifacts;
+pub mod attestation;
+pub mod depcache;
+pub mod diagnostics;
+pub mod ids;
+pub mod limits;
+pub mod lockfile;
+pub mod metadata;
+pub mod policy;
+pub mod receipt;
+pub mod registry;
+pub mod reproducible;
+pub mod sandbox;
+pub mod script;
+pub mod toolchain;
+pub mod verify;
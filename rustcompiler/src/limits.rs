@@ -0,0 +1,371 @@
+// Per-execution resource limits
+//
+// Wall-clock timeouts and the output caps are enforced by the service while it
+// supervises the job. CPU time is an rlimit applied to the user program only,
+// through the tee-exec helper that starts the compiled binary, so cargo and
+// rustc themselves are not affected. Memory and process count are the
+// memory.max and pids.max of a cgroup v2 tee-exec creates for each run, when
+// the service could set one up; otherwise they fall back to rlimits.
+
+use crate::sandbox::ExitStats;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    BuildTimeout,
+    RunTimeout,
+    CpuTime,
+    Memory,
+    Processes,
+    OutputSize,
+    BuildOutputSize,
+}
+
+impl LimitKind {
+    pub fn describe(self, limits: &ResourceLimits) -> String {
+        match self {
+            LimitKind::BuildTimeout => format!("Build exceeded the {}s wall-clock timeout", limits.build_timeout_secs),
+            LimitKind::RunTimeout => format!("Run exceeded the {}s wall-clock timeout", limits.run_timeout_secs),
+            LimitKind::CpuTime => format!("Program exceeded the {}s CPU time limit", limits.cpu_secs.unwrap_or_default()),
+            LimitKind::Memory => format!("Program exceeded the {} byte memory limit", limits.memory_bytes.unwrap_or_default()),
+            LimitKind::Processes => format!("Program exceeded the {} process limit", limits.max_processes.unwrap_or_default()),
+            LimitKind::OutputSize => format!("Program output exceeded {} bytes", limits.max_output_bytes),
+            LimitKind::BuildOutputSize => format!("Build output exceeded {} bytes", limits.max_build_output_bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ResourceLimits {
+    pub build_timeout_secs: u64,
+    pub run_timeout_secs: u64,
+    pub cpu_secs: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub max_processes: Option<u64>,
+    pub max_output_bytes: u64,
+    pub max_build_output_bytes: u64,
+}
+
+// Limits requested for a single run. They can only tighten the service limits.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct LimitOverrides {
+    pub build_timeout_secs: Option<u64>,
+    pub run_timeout_secs: Option<u64>,
+    pub cpu_secs: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub max_processes: Option<u64>,
+    pub max_output_bytes: Option<u64>,
+    pub max_build_output_bytes: Option<u64>,
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    std::env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+// An env var set to 0 disables an optional limit
+fn env_optional_u64(name: &str, default: u64) -> Option<u64> {
+    match env_u64(name).unwrap_or(default) {
+        0 => None,
+        value => Some(value),
+    }
+}
+
+fn tighten(limit: Option<u64>, requested: Option<u64>) -> Option<u64> {
+    match (limit, requested) {
+        (Some(limit), Some(requested)) => Some(limit.min(requested)),
+        (None, requested) => requested,
+        (limit, None) => limit,
+    }
+}
+
+impl ResourceLimits {
+    pub fn from_env() -> Self {
+        Self {
+            build_timeout_secs: env_u64("BUILD_TIMEOUT_SECS").unwrap_or(600),
+            run_timeout_secs: env_u64("RUN_TIMEOUT_SECS").unwrap_or(60),
+            cpu_secs: env_optional_u64("RUN_CPU_SECS", 60),
+            memory_bytes: env_optional_u64("RUN_MEMORY_BYTES", 1024 * 1024 * 1024),
+            max_processes: env_optional_u64("RUN_MAX_PROCESSES", 64),
+            max_output_bytes: env_u64("MAX_OUTPUT_BYTES").unwrap_or(1024 * 1024),
+            max_build_output_bytes: env_u64("MAX_BUILD_OUTPUT_BYTES").unwrap_or(16 * 1024 * 1024),
+        }
+    }
+
+    pub fn tightened(&self, overrides: &LimitOverrides) -> Self {
+        Self {
+            build_timeout_secs: self.build_timeout_secs.min(overrides.build_timeout_secs.unwrap_or(u64::MAX)),
+            run_timeout_secs: self.run_timeout_secs.min(overrides.run_timeout_secs.unwrap_or(u64::MAX)),
+            cpu_secs: tighten(self.cpu_secs, overrides.cpu_secs),
+            memory_bytes: tighten(self.memory_bytes, overrides.memory_bytes),
+            max_processes: tighten(self.max_processes, overrides.max_processes),
+            max_output_bytes: self.max_output_bytes.min(overrides.max_output_bytes.unwrap_or(u64::MAX)),
+            max_build_output_bytes: self.max_build_output_bytes.min(overrides.max_build_output_bytes.unwrap_or(u64::MAX)),
+        }
+    }
+
+    pub fn build_timeout(&self) -> Duration {
+        Duration::from_secs(self.build_timeout_secs)
+    }
+
+    pub fn run_timeout(&self) -> Duration {
+        Duration::from_secs(self.run_timeout_secs)
+    }
+
+    // Arguments for tee-exec; `cgroup` is the parent of the per-run cgroups
+    pub fn exec_args(&self, cgroup: Option<&Path>) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cgroup) = cgroup {
+            args.extend(["--cgroup".to_string(), cgroup.to_string_lossy().into_owned()]);
+        }
+        if let Some(cpu) = self.cpu_secs {
+            args.extend(["--cpu".to_string(), cpu.to_string()]);
+        }
+        if let Some(memory) = self.memory_bytes {
+            args.extend(["--memory".to_string(), memory.to_string()]);
+        }
+        if let Some(processes) = self.max_processes {
+            args.extend(["--nproc".to_string(), processes.to_string()]);
+        }
+        args
+    }
+
+    // The limit the kernel stopped a program for, from how it ended and what
+    // tee-exec measured; never from its output, which the program controls.
+    // The cgroup OOM killer sends SIGKILL; a fork rejected by pids.max fails
+    // inside the program, so it counts when the program then failed.
+    // RLIMIT_CPU sends SIGXCPU at the soft limit and SIGKILL one second later.
+    // The rlimit fallbacks for memory and processes leave no such trace and
+    // are not attributed.
+    pub fn enforced_limit(&self, stats: &ExitStats) -> Option<LimitKind> {
+        if self.memory_bytes.is_some() && stats.oom_kills > 0 && stats.signal == Some(libc::SIGKILL) {
+            return Some(LimitKind::Memory);
+        }
+        if self.max_processes.is_some() && stats.rejected_forks > 0 && stats.exit_code != Some(0) {
+            return Some(LimitKind::Processes);
+        }
+        let cpu_secs = self.cpu_secs?;
+        let killed = matches!(stats.signal, Some(libc::SIGXCPU) | Some(libc::SIGKILL));
+        (killed && stats.cpu_time_ms >= cpu_secs.saturating_mul(1000)).then_some(LimitKind::CpuTime)
+    }
+}
+
+fn set_rlimit(resource: libc::__rlimit_resource_t, soft: u64, hard: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Applies the rlimits to the current process; used by tee-exec right before
+// it execs the user program. RLIMIT_NPROC is not enforced for processes of
+// host uid 0, which includes the sandbox's uid when the service runs as root.
+pub fn apply_rlimits(cpu_secs: Option<u64>, memory_bytes: Option<u64>, max_processes: Option<u64>) -> std::io::Result<()> {
+    if let Some(cpu) = cpu_secs {
+        // SIGXCPU at the soft limit, SIGKILL one second later
+        set_rlimit(libc::RLIMIT_CPU, cpu, cpu + 1)?;
+    }
+    if let Some(memory) = memory_bytes {
+        set_rlimit(libc::RLIMIT_AS, memory, memory)?;
+    }
+    if let Some(processes) = max_processes {
+        set_rlimit(libc::RLIMIT_NPROC, processes, processes)?;
+    }
+    Ok(())
+}
+
+// cgroup v2 hierarchy and the controllers the run cgroups need
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+const RUN_CONTROLLERS: &str = "+memory +pids";
+// Leaf the service's own processes move to
+const SERVICE_CGROUP: &str = "service";
+// tee-exec's child and the sandbox's init run in the cgroup as well
+const HELPER_PROCESSES: u64 = 2;
+
+fn cgroup_write(dir: &Path, file: &str, value: &str) -> Result<(), String> {
+    let path = dir.join(file);
+    std::fs::write(&path, value).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// The cgroup v2 the current process is in
+fn current_cgroup() -> Result<PathBuf, String> {
+    let entries = std::fs::read_to_string("/proc/self/cgroup")
+        .map_err(|e| format!("Failed to read /proc/self/cgroup: {}", e))?;
+    let relative = entries
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("No cgroup v2 hierarchy")?;
+    Ok(Path::new(CGROUP_MOUNT).join(relative.trim_start_matches('/')))
+}
+
+// Sets up the cgroup tee-exec creates run cgroups under and returns it. A
+// cgroup can only hand controllers to its children when it has no processes
+// itself, so every process of the service's cgroup moves to a `service` leaf
+// first; on a restart the service already is in that leaf.
+pub fn setup_run_cgroups() -> Result<PathBuf, String> {
+    let mut base = current_cgroup()?;
+    if base.ends_with(SERVICE_CGROUP) {
+        base.pop();
+    }
+    let controllers = std::fs::read_to_string(base.join("cgroup.controllers"))
+        .map_err(|e| format!("No cgroup v2 controllers at {}: {}", base.display(), e))?;
+    for controller in ["memory", "pids"] {
+        if !controllers.split_whitespace().any(|available| available == controller) {
+            return Err(format!("The {} controller is not available in {}", controller, base.display()));
+        }
+    }
+
+    let service = base.join(SERVICE_CGROUP);
+    let runs = base.join("runs");
+    for dir in [&service, &runs] {
+        if !dir.exists() {
+            std::fs::create_dir(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+    }
+    let processes = std::fs::read_to_string(base.join("cgroup.procs"))
+        .map_err(|e| format!("Failed to read {}: {}", base.join("cgroup.procs").display(), e))?;
+    for pid in processes.lines() {
+        // Processes that exited meanwhile can't be moved, which is fine
+        let _ = cgroup_write(&service, "cgroup.procs", pid);
+    }
+    cgroup_write(&base, "cgroup.subtree_control", RUN_CONTROLLERS)?;
+    cgroup_write(&runs, "cgroup.subtree_control", RUN_CONTROLLERS)?;
+    Ok(runs)
+}
+
+// cgroup of a single run, created by tee-exec. tee-exec itself stays outside,
+// so it can read the limit events and remove the cgroup once the run ends.
+pub struct RunCgroup {
+    path: PathBuf,
+}
+
+impl RunCgroup {
+    pub fn create(parent: &Path, memory_bytes: Option<u64>, max_processes: Option<u64>) -> Result<Self, String> {
+        let path = parent.join(format!("run-{}", std::process::id()));
+        std::fs::create_dir(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let cgroup = Self { path };
+
+        if let Some(memory) = memory_bytes {
+            cgroup_write(&cgroup.path, "memory.max", &memory.to_string())?;
+            // Only present with swap accounting; without it there is no swap to limit
+            let _ = cgroup_write(&cgroup.path, "memory.swap.max", "0");
+        }
+        if let Some(processes) = max_processes {
+            cgroup_write(&cgroup.path, "pids.max", &processes.saturating_add(HELPER_PROCESSES).to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    // Moves the calling process into the cgroup; everything it starts from
+    // then on is accounted there
+    pub fn enter(&self) -> Result<(), String> {
+        cgroup_write(&self.path, "cgroup.procs", "0")
+    }
+
+    fn event_count(&self, file: &str, key: &str) -> u64 {
+        std::fs::read_to_string(self.path.join(file))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    let (name, count) = line.split_once(' ')?;
+                    (name == key).then(|| count.trim().parse().ok()).flatten()
+                })
+            })
+            .unwrap_or(0)
+    }
+
+    // Adds the OOM kills and the forks pids.max rejected during the run
+    pub fn record_events(&self, stats: &mut ExitStats) {
+        stats.oom_kills = self.event_count("memory.events", "oom_kill");
+        stats.rejected_forks = self.event_count("pids.events", "max");
+    }
+}
+
+impl Drop for RunCgroup {
+    fn drop(&mut self) {
+        // Processes the program left behind without the sandbox's PID
+        // namespace would keep the cgroup busy
+        let _ = cgroup_write(&self.path, "cgroup.kill", "1");
+        for _ in 0..50 {
+            if std::fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        eprintln!("tee-exec: Failed to remove {}", self.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(cpu_secs: Option<u64>) -> ResourceLimits {
+        ResourceLimits {
+            build_timeout_secs: 600,
+            run_timeout_secs: 60,
+            cpu_secs,
+            memory_bytes: Some(1 << 30),
+            max_processes: Some(64),
+            max_output_bytes: 1 << 20,
+            max_build_output_bytes: 16 << 20,
+        }
+    }
+
+    fn stats(exit_code: Option<i32>, signal: Option<i32>, cpu_time_ms: u64) -> ExitStats {
+        ExitStats { exit_code, signal, cpu_time_ms, ..ExitStats::default() }
+    }
+
+    #[test]
+    fn cpu_limit_needs_the_signal_and_the_cpu_time() {
+        let limits = limits(Some(2));
+        assert_eq!(limits.enforced_limit(&stats(None, Some(libc::SIGXCPU), 2000)), Some(LimitKind::CpuTime));
+        assert_eq!(limits.enforced_limit(&stats(None, Some(libc::SIGKILL), 3000)), Some(LimitKind::CpuTime));
+        // A program raising SIGXCPU itself, or killed early for another reason
+        assert_eq!(limits.enforced_limit(&stats(None, Some(libc::SIGXCPU), 10)), None);
+        assert_eq!(limits.enforced_limit(&stats(None, Some(libc::SIGKILL), 1999)), None);
+        assert_eq!(limits.enforced_limit(&stats(None, Some(libc::SIGABRT), 5000)), None);
+        assert_eq!(limits.enforced_limit(&stats(Some(0), None, 5000)), None);
+    }
+
+    #[test]
+    fn no_cpu_limit_is_never_attributed() {
+        assert_eq!(limits(None).enforced_limit(&stats(None, Some(libc::SIGXCPU), 60_000)), None);
+    }
+
+    #[test]
+    fn cgroup_events_attribute_memory_and_processes() {
+        let limits = limits(Some(2));
+        let oom = ExitStats { oom_kills: 1, ..stats(None, Some(libc::SIGKILL), 10) };
+        assert_eq!(limits.enforced_limit(&oom), Some(LimitKind::Memory));
+        // The OOM killer always sends SIGKILL
+        let aborted = ExitStats { oom_kills: 1, ..stats(None, Some(libc::SIGABRT), 10) };
+        assert_eq!(limits.enforced_limit(&aborted), None);
+
+        let forks = ExitStats { rejected_forks: 3, ..stats(Some(101), None, 10) };
+        assert_eq!(limits.enforced_limit(&forks), Some(LimitKind::Processes));
+        // A program that coped with the failed forks succeeded
+        let coped = ExitStats { rejected_forks: 3, ..stats(Some(0), None, 10) };
+        assert_eq!(limits.enforced_limit(&coped), None);
+    }
+
+    #[test]
+    fn overrides_only_tighten() {
+        let overrides = LimitOverrides {
+            run_timeout_secs: Some(600),
+            cpu_secs: Some(5),
+            memory_bytes: Some(1 << 40),
+            ..LimitOverrides::default()
+        };
+        let tightened = limits(None).tightened(&overrides);
+        assert_eq!(tightened.run_timeout_secs, 60);
+        assert_eq!(tightened.cpu_secs, Some(5));
+        assert_eq!(tightened.memory_bytes, Some(1 << 30));
+        assert_eq!(tightened.max_processes, Some(64));
+    }
+}
@@ -0,0 +1,99 @@
+// Packages pinned by a project's Cargo.lock
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    // None for crates of the project itself (path dependencies)
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+}
+
+// Why a build that requires a lockfile was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockfileProblem {
+    // No Cargo.lock was uploaded
+    Missing,
+    // Cargo.lock does not match Cargo.toml and cargo would have to update it
+    Stale,
+}
+
+// What cargo says when --locked forbids updating the lockfile
+pub fn is_stale_error(cargo_stderr: &str) -> bool {
+    cargo_stderr.contains("--locked was passed")
+}
+
+#[derive(serde::Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+pub fn parse(text: &str) -> Result<Vec<LockedPackage>, String> {
+    toml::from_str::<Lockfile>(text)
+        .map(|lockfile| lockfile.package)
+        .map_err(|e| format!("Invalid Cargo.lock: {}", e))
+}
+
+const CRATES_IO_SOURCES: [&str; 2] = [
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+impl LockedPackage {
+    pub fn is_crates_io(&self) -> bool {
+        self.source.as_deref().is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["itoa"]
+
+[[package]]
+name = "itoa"
+version = "1.0.11"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "49f1f14873335454500d59611f1cf4a4b0f786f9ac11f4312a78e4cf2566695b"
+
+[[package]]
+name = "fork"
+version = "0.2.0"
+source = "git+https://example.com/fork?rev=abc#abc"
+"#;
+
+    #[test]
+    fn parses_locked_packages() {
+        let packages = parse(LOCKFILE).unwrap();
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[0], LockedPackage { name: "app".to_string(), version: "0.1.0".to_string(), source: None, checksum: None });
+        assert!(packages[1].checksum.is_some());
+        assert_eq!(parse("version = 4\n").unwrap(), Vec::new());
+        assert!(parse("[[package]]\nname = ").is_err());
+    }
+
+    #[test]
+    fn only_the_crates_io_index_is_crates_io() {
+        let packages = parse(LOCKFILE).unwrap();
+        let crates_io: Vec<bool> = packages.iter().map(LockedPackage::is_crates_io).collect();
+        assert_eq!(crates_io, [false, true, false]);
+        let sparse = LockedPackage { source: Some("sparse+https://index.crates.io/".to_string()), ..packages[1].clone() };
+        assert!(sparse.is_crates_io());
+    }
+
+    #[test]
+    fn recognizes_cargo_refusing_to_update_the_lockfile() {
+        let stderr = "error: the lock file /work/Cargo.lock needs to be updated but --locked was passed to prevent this";
+        assert!(is_stale_error(stderr));
+        assert!(!is_stale_error("error: failed to select a version for the requirement `itoa = \"^9\"`"));
+    }
+}
@@ -1,27 +1,52 @@
+mod jobs;
+mod outcome;
 mod types;
+mod upload;
 
 use axum::{
     extract::Path,
     body::Body,
-    response::Response,
+    response::{IntoResponse, Response},
     http::header,
-    extract::Multipart,
+    extract::{DefaultBodyLimit, Multipart},
     routing::{post, get},
     Router,
     http::StatusCode,
 };
 use tokio::process::Command as TokioCommand;
-use serde_json;
 use std::fs;
 use std::env;
 use tokio::net::TcpListener;
 use tower_http::cors::{CorsLayer, Any};
 use dotenv::dotenv;
 use crate::types::*;
-use std::path::Path as StdPath;
+use crate::jobs::{ExitEvent, Job, JobEvent, JobManager, JobResult, LogLine, LogStream, Phase, ProcessOutput};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use std::convert::Infallible;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path as StdPath, PathBuf};
 use std::sync::Arc;
-use chrono;
-use lazy_static::lazy_static;
+use rust_tee_compiler::archive::{self, ExtractLimits};
+use rust_tee_compiler::depcache::{DependencyCache, SharedCrates};
+use rust_tee_compiler::diagnostics::{self, Diagnostic};
+use rust_tee_compiler::artifacts::{self, ArtifactCache, BuildInfo, CachedBuild};
+use rust_tee_compiler::ids::{ProjectId, UserId};
+use rust_tee_compiler::attestation::{self, AttestationProvider, ExecutedCode, ExecutionInputs, TdxQuote, execution_report_data_from_digest, receipt_key_report_data};
+use rust_tee_compiler::limits::{self, LimitOverrides, ResourceLimits};
+use rust_tee_compiler::metadata::{Metadata, RunnableKind, RunnableTarget};
+use rust_tee_compiler::policy::{BuildPolicy, DependencyPolicy};
+use rust_tee_compiler::registry::RegistryMirror;
+use rust_tee_compiler::reproducible::{self, BuildPaths};
+use rust_tee_compiler::lockfile::{self, LockfileProblem};
+use rust_tee_compiler::sandbox::{self, BindMount, ExitStats, SandboxPolicy};
+use rust_tee_compiler::script;
+use rust_tee_compiler::toolchain::{self, InstalledToolchains, Toolchain};
+use rust_tee_compiler::receipt::{ExecutionReceipt, ReceiptSigner, RECEIPT_VERSION, sha256_hex};
+use crate::outcome::Outcome;
+use crate::upload::{ProjectLocks, StagedArchive, StagedInputs};
+use std::collections::BTreeMap;
+use base64::Engine;
+use sha2::{Digest, Sha256};
 
 
 // Global Walrus client
@@ -32,9 +57,96 @@ lazy_static::lazy_static! {
     };
 }
 
+// Global attestation provider (TDX via configfs-tsm, or mock for local runs).
+// Without one the service still starts, but every request that needs a quote
+// fails with attestation_unavailable.
+lazy_static::lazy_static! {
+    static ref ATTESTATION_PROVIDER: Result<Arc<dyn AttestationProvider>, String> = match attestation::provider_from_env() {
+        Ok(provider) => {
+            println!("Using {} attestation provider", provider.name());
+            Ok(Arc::from(provider))
+        }
+        Err(e) => {
+            println!("WARNING: No attestation provider, runs and /attestation/pubkey will fail: {}", e);
+            Err(e)
+        }
+    };
+}
+
+// Job registry; MAX_CONCURRENT_JOBS bounds how many projects build or run at once
+lazy_static::lazy_static! {
+    static ref JOB_MANAGER: JobManager = {
+        let max_concurrent = env::var("MAX_CONCURRENT_JOBS").ok().and_then(|v| v.parse().ok()).unwrap_or(2);
+        let retention_secs = env::var("JOB_RETENTION_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600);
+        JobManager::new(max_concurrent, std::time::Duration::from_secs(retention_secs))
+    };
+}
+
+// Service-wide resource limits, extraction caps and sandbox/build policies;
+// requests can only tighten the limits
+lazy_static::lazy_static! {
+    static ref SERVICE_LIMITS: ResourceLimits = ResourceLimits::from_env();
+    // Parent of the per-run cgroups enforcing the memory and process limits
+    static ref RUN_CGROUPS: Option<PathBuf> = match limits::setup_run_cgroups() {
+        Ok(cgroups) => {
+            println!("Enforcing memory and process limits with cgroups under {}", cgroups.display());
+            Some(cgroups)
+        }
+        Err(e) => {
+            println!("WARNING: {}; memory and process limits fall back to rlimits, and RLIMIT_NPROC does not apply to root", e);
+            None
+        }
+    };
+    static ref EXTRACT_LIMITS: ExtractLimits = ExtractLimits::from_env();
+    static ref SANDBOX_POLICY: SandboxPolicy = SandboxPolicy::from_env();
+    static ref BUILD_POLICY: BuildPolicy = BuildPolicy::from_env();
+    static ref REGISTRY_MIRROR: Option<RegistryMirror> = RegistryMirror::from_env();
+    // REQUIRE_LOCKFILE=true builds every project with --locked
+    static ref REQUIRE_LOCKFILE: bool = env::var("REQUIRE_LOCKFILE").map(|v| v == "true").unwrap_or(false);
+    // REPRODUCIBLE_BUILDS=true builds every project in reproducible mode
+    static ref REPRODUCIBLE_BUILDS: bool = env::var("REPRODUCIBLE_BUILDS").map(|v| v == "true").unwrap_or(false);
+    static ref DEPENDENCY_POLICY: DependencyPolicy = DependencyPolicy::from_env()
+        .unwrap_or_else(|e| panic!("{}", e));
+}
+
+// Compiled binaries by source hash, shared by all users
+lazy_static::lazy_static! {
+    static ref PROJECT_LOCKS: ProjectLocks = ProjectLocks::default();
+    static ref ARTIFACT_CACHE: ArtifactCache = ArtifactCache::from_env();
+    static ref DEPENDENCY_CACHE: DependencyCache = DependencyCache::from_env();
+}
+
+// Receipt signing key, generated inside the enclave and never persisted
+lazy_static::lazy_static! {
+    static ref RECEIPT_SIGNER: ReceiptSigner = ReceiptSigner::generate();
+}
+
+// Toolchains installed when the service started. Projects without a
+// toolchain of their own use rustup's default, or in reproducible mode the one
+// pinned by REPRODUCIBLE_TOOLCHAIN (e.g. 1.95.0).
+lazy_static::lazy_static! {
+    static ref TOOLCHAINS: InstalledToolchains = InstalledToolchains::detect()
+        .unwrap_or_else(|e| panic!("{}", e));
+    static ref REPRODUCIBLE_TOOLCHAIN: Toolchain = match env::var("REPRODUCIBLE_TOOLCHAIN").ok().filter(|v| !v.is_empty()) {
+        Some(name) => TOOLCHAINS.find(&name).cloned()
+            .unwrap_or_else(|| panic!("REPRODUCIBLE_TOOLCHAIN {} is not installed", name)),
+        None => TOOLCHAINS.default.clone(),
+    };
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    // Fail at startup rather than on the first upload if the policy file is broken
+    lazy_static::initialize(&DEPENDENCY_POLICY);
+    lazy_static::initialize(&REPRODUCIBLE_TOOLCHAIN);
+    lazy_static::initialize(&ATTESTATION_PROVIDER);
+    lazy_static::initialize(&RUN_CGROUPS);
+    if SANDBOX_POLICY.enabled {
+        check_sandbox().unwrap_or_else(|e| panic!("{}", e));
+    }
+    println!("Default toolchain: {}", TOOLCHAINS.default.version);
+    println!("Installed toolchains: {}", TOOLCHAINS.names().join(", "));
     
     let allowed_origins_str = env::var("CORS_ALLOWED_ORIGIN")
         .unwrap_or_else(|_| "*".to_string());
@@ -48,9 +160,20 @@ async fn main() {
             "Rust Compiler API is running"
         }))
         .route("/run/{user_id}/{project_id}", post(run_project))
+        .route("/run/{user_id}/{project_id}/stream", post(run_project_stream))
+        .route("/run/{user_id}/{project_id}/source", post(run_source))
+        .route("/build/{user_id}/{project_id}", post(build_only))
+        .route("/execute/{user_id}/{project_id}", post(execute_build))
+        .route("/jobs", post(create_job))
+        .route("/jobs/{job_id}", get(get_job).delete(cancel_job))
+        .route("/jobs/{job_id}/events", get(stream_job_events))
+        .route("/attestation/pubkey", get(get_attestation_pubkey))
         .route("/walrus/upload", post(upload_to_walrus))
         .route("/walrus/retrieve/{blob_id}", get(retrieve_from_walrus))
         .route("/walrus/info/{blob_id}", get(get_walrus_info))
+        // Uploads are capped by EXTRACT_MAX_BYTES instead of axum's 2 MB default;
+        // the slack leaves room for the other fields so the archive cap trips first
+        .layer(DefaultBodyLimit::max(EXTRACT_LIMITS.max_total_bytes.saturating_add(1024 * 1024).try_into().unwrap_or(usize::MAX)))
         .layer(cors);
     
     let port = env::var("PORT").unwrap_or_else(|_| "3001".to_string());
@@ -78,25 +201,239 @@ fn create_cors_layer(allowed_origins: &str) -> CorsLayer {
     }
 }
 
-pub async fn run_project(
-    Path((user_id, project_id)): Path<(String, String)>,
-    mut multipart: Multipart,
-) -> Result<Response<Body>, (StatusCode, String)> {
+// Fields of a project upload, shared by /run, /build, /execute and /jobs
+struct ProjectUpload {
+    user_id: Option<UserId>,
+    project_id: Option<ProjectId>,
+    archive: Option<StagedArchive>,
+    source_sha256: Option<[u8; 32]>,
+    args: Vec<String>,
+    limits: LimitOverrides,
+    locked: bool,
+    reproducible: bool,
+    toolchain: Option<String>,
+    profile: Option<String>,
+    features: Vec<String>,
+    no_default_features: bool,
+    bin: Option<String>,
+    example: Option<String>,
+    input: ProgramInput,
+}
+
+// What a run feeds the program besides its args
+#[derive(Default)]
+struct ProgramInput {
+    stdin: Option<Vec<u8>>,
+    // Only names allowed by SANDBOX_ENV_ALLOWLIST
+    env: BTreeMap<String, String>,
+    // Mounted read-only into the program's working directory
+    files: StagedInputs,
+}
+
+impl ProgramInput {
+    fn is_empty(&self) -> bool {
+        self.stdin.is_none() && self.env.is_empty() && self.files.files.is_empty()
+    }
     
-    println!("=== run_project called for user: {}, project: {} ===", user_id, project_id);
+    // Rejects variables the sandbox policy does not allow
+    fn set_env(&mut self, env: BTreeMap<String, String>) -> Result<(), (StatusCode, String)> {
+        let denied: Vec<&String> = env.keys().filter(|name| !SANDBOX_POLICY.allows_env(name)).collect();
+        if !denied.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, format!(
+                "Environment variables {:?} are not allowed; allowed: {:?}", denied, SANDBOX_POLICY.env_allowlist
+            )));
+        }
+        self.env = env;
+        Ok(())
+    }
     
-    let project_dir = format!("./projects/{}/{}", user_id, project_id);
-    println!("Project directory: {}", project_dir);
+    fn set_stdin(&mut self, stdin: Vec<u8>) -> Result<(), (StatusCode, String)> {
+        if stdin.len() as u64 > EXTRACT_LIMITS.max_file_bytes {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("stdin exceeds {} bytes", EXTRACT_LIMITS.max_file_bytes)));
+        }
+        self.stdin = Some(stdin);
+        Ok(())
+    }
+}
+
+// How a project is built; requests can only ask for stricter builds than
+// the service settings
+#[derive(Debug, Clone)]
+struct BuildOptions {
+    // Build with --locked; the upload must carry an up-to-date Cargo.lock
+    locked: bool,
+    // Normalize paths, timestamps and codegen so the binary can be rebuilt
+    // bit for bit elsewhere; implies locked
+    reproducible: bool,
+    toolchain: Toolchain,
+    // Whether the request named the toolchain with the `toolchain` field
+    toolchain_requested: bool,
+    // Cargo profile: release unless the request picks dev or a custom one
+    profile: String,
+    features: Vec<String>,
+    no_default_features: bool,
+    // Binary or example to build; None lets the project's targets decide
+    target: Option<(RunnableKind, String)>,
+}
+
+impl BuildOptions {
+    fn from_upload(upload: &ProjectUpload) -> Result<Self, CompilerApiError> {
+        let reproducible = *REPRODUCIBLE_BUILDS || upload.reproducible;
+        let toolchain = match upload.toolchain.as_deref() {
+            Some(requested) => installed_toolchain(requested, "the toolchain field")?,
+            None if reproducible => REPRODUCIBLE_TOOLCHAIN.clone(),
+            None => TOOLCHAINS.default.clone(),
+        };
+        let target = match (&upload.bin, &upload.example) {
+            (Some(_), Some(_)) => {
+                return Err((StatusCode::BAD_REQUEST, "Pick either bin or example, not both".to_string()).into());
+            }
+            (Some(bin), None) => Some((RunnableKind::Bin, bin.clone())),
+            (None, Some(example)) => Some((RunnableKind::Example, example.clone())),
+            (None, None) => None,
+        };
+        let mut features = upload.features.clone();
+        features.sort();
+        features.dedup();
+        Ok(Self {
+            locked: *REQUIRE_LOCKFILE || upload.locked || reproducible,
+            reproducible,
+            toolchain,
+            toolchain_requested: upload.toolchain.is_some(),
+            profile: upload.profile.clone().unwrap_or_else(|| "release".to_string()),
+            features,
+            no_default_features: upload.no_default_features,
+            target,
+        })
+    }
+    
+    // Artifact cache variant; builds with other settings are cached apart.
+    // A toolchain file is part of the sources and needs no variant.
+    fn variant(&self) -> String {
+        let mut parts = Vec::new();
+        if self.reproducible {
+            parts.push("reproducible".to_string());
+        }
+        if self.toolchain_requested {
+            parts.push(format!("toolchain={}", self.toolchain.verbose_version));
+        }
+        if self.profile != "release" {
+            parts.push(format!("profile={}", self.profile));
+        }
+        if !self.features.is_empty() {
+            parts.push(format!("features={}", self.features.join(",")));
+        }
+        if self.no_default_features {
+            parts.push("no-default-features".to_string());
+        }
+        if let Some((kind, name)) = &self.target {
+            parts.push(format!("{}={}", kind.as_str(), name));
+        }
+        parts.join("\n")
+    }
+    
+    // Names the shared target directory
+    fn cache_profile(&self) -> String {
+        if self.reproducible {
+            format!("{}-reproducible", self.profile)
+        } else {
+            self.profile.clone()
+        }
+    }
+    
+    // Feature selection, for reading metadata and for compiling
+    fn feature_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args
+    }
+    
+    // Profile, features and target of the compile step
+    fn compile_args(&self, target: &RunnableTarget) -> Vec<String> {
+        let mut args = vec!["--profile".to_string(), self.profile.clone()];
+        args.extend(self.feature_args());
+        args.push("--package".to_string());
+        args.push(target.package.clone());
+        args.push(target.kind.cargo_flag().to_string());
+        args.push(target.name.clone());
+        args
+    }
+    
+    // Environment for every cargo command of the build
+    fn cargo_env(&self) -> Vec<(String, String)> {
+        self.toolchain.cargo_env()
+    }
     
-    // Create the project directory and src subdirectory
-    println!("Creating project directory...");
-    fs::create_dir_all(format!("{}/src", project_dir))
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create project directory {}: {}", project_dir, e)))?;
-    println!("Project directory created successfully");
+    // Extra arguments for every cargo command of the build
+    fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.locked {
+            args.push("--locked".to_string());
+        }
+        args
+    }
+}
+
+// Settings for cargo commands that run on the host, outside the sandbox.
+// Uploads cannot carry a .cargo directory, and these override whatever
+// else cargo might pick up that would make it run a command: rustc
+// wrappers and the git CLI. RUSTUP_TOOLCHAIN from the toolchain already
+// keeps rustup from reading the project's rust-toolchain file.
+fn host_cargo(command: &mut TokioCommand) -> &mut TokioCommand {
+    command
+        .arg("--config").arg("net.git-fetch-with-cli=false")
+        .arg("--config").arg("build.rustc-wrapper=''")
+        .arg("--config").arg("build.rustc-workspace-wrapper=''")
+        .env("CARGO_HOME", reproducible::cargo_home())
+        .env("RUSTC_WRAPPER", "")
+        .env("RUSTC_WORKSPACE_WRAPPER", "")
+        .env("CARGO_BUILD_RUSTC_WRAPPER", "")
+        .env("CARGO_BUILD_RUSTC_WORKSPACE_WRAPPER", "")
+}
+
+// A project that has been unpacked and checked, ready to be built
+struct PreparedProject {
+    project_dir: String,
+    // Held for the whole job, which builds and runs in the project directory
+    _project_lock: tokio::sync::OwnedMutexGuard<()>,
+    tarball_sha256: [u8; 32],
+    source_sha256: [u8; 32],
+    // Whether the upload had a Cargo.lock, or cargo generates one
+    lockfile_uploaded: bool,
+    build: BuildOptions,
+    args: Vec<String>,
+    limits: ResourceLimits,
+    input: ProgramInput,
+}
+
+async fn read_project_upload(multipart: &mut Multipart) -> Result<ProjectUpload, CompilerApiError> {
+    let mut upload = ProjectUpload {
+        user_id: None,
+        project_id: None,
+        archive: None,
+        source_sha256: None,
+        args: Vec::new(),
+        limits: LimitOverrides::default(),
+        locked: false,
+        reproducible: false,
+        toolchain: None,
+        profile: None,
+        features: Vec::new(),
+        no_default_features: false,
+        bin: None,
+        example: None,
+        input: ProgramInput::default(),
+    };
     
-    // Track if we received a tar file
-    let mut has_tar_file = false;
-    let mut args = Vec::new();
+    // args and run_request both set argv; only one of them may be given
+    let mut args_field = false;
+    let mut run_request = None::<RunRequest>;
     
     println!("Starting to process multipart upload...");
     // This is commonly used for file upload, reference: https://docs.rs/axum/0.8.1/axum/extract/struct.Multipart.html
@@ -109,97 +446,787 @@ pub async fn run_project(
         
         println!("Processing field: {}", file_name);
         
-        if file_name == "tar_file" {
-            let file_data = field.bytes().await.map_err(|e| 
-                (StatusCode::BAD_REQUEST, format!("Failed to read field data: {}", e))
-            )?;
-            
-            println!("Field data size: {} bytes", file_data.len());
-            
-            // Save the tar file
-            let tar_path = format!("{}/project.tar.gz", project_dir);
-            println!("Saving tar file to: {}", tar_path);
-            tokio::fs::write(&tar_path, &file_data)
-                .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write tar file: {}", e)))?;
-            has_tar_file = true;
-            println!("Tar file saved successfully");
-        } else if file_name == "args" {
-            let args_data = field.text().await.map_err(|e| 
-                (StatusCode::BAD_REQUEST, format!("Failed to read args data: {}", e))
-            )?;
-            
-            println!("Args received: {}", args_data);
-            args = args_data.split_whitespace().map(|s| s.to_string()).collect();
-        } else {
-            println!("Unknown field: {}", file_name);
-            return Err((StatusCode::BAD_REQUEST, format!("Unknown field: {}", file_name)));
+        match file_name.as_str() {
+            "tar_file" => {
+                // Unpacked while it streams in, checking every entry before it is written
+                let staged = upload::stage_archive(field, &EXTRACT_LIMITS).await?;
+                if let Some(format) = staged.format {
+                    println!("Extracted {:?} archive", format);
+                }
+                upload.archive = Some(staged);
+            },
+            "args" => {
+                let args_data = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read args data: {}", e))
+                )?;
+                
+                println!("Args received: {}", args_data);
+                upload.args = args_data.split_whitespace().map(|s| s.to_string()).collect();
+                args_field = true;
+            },
+            "run_request" => {
+                let request_data = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read run_request: {}", e))
+                )?;
+                
+                run_request = Some(serde_json::from_str(&request_data).map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid run_request: {}", e))
+                )?);
+            },
+            "limits" => {
+                let limits_data = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read limits data: {}", e))
+                )?;
+                
+                println!("Limits received: {}", limits_data);
+                upload.limits = serde_json::from_str(&limits_data).map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid limits: {}", e))
+                )?;
+            },
+            "stdin" => {
+                let data = field.bytes().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read stdin: {}", e))
+                )?;
+                
+                upload.input.set_stdin(data.to_vec())?;
+            },
+            "env" => {
+                let env_data = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read env: {}", e))
+                )?;
+                
+                let env = serde_json::from_str(&env_data).map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid env: expected an object of strings: {}", e))
+                )?;
+                upload.input.set_env(env)?;
+            },
+            "input" => {
+                // One field per file, named by its file name
+                let name = field.file_name()
+                    .ok_or_else(|| (StatusCode::BAD_REQUEST, "Input files need a file name".to_string()))?
+                    .to_string();
+                let data = field.bytes().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read input file {}: {}", name, e))
+                )?;
+                
+                upload.input.files.add(&name, &data, &EXTRACT_LIMITS).await?;
+                println!("Input file received: {} ({} bytes)", name, data.len());
+            },
+            "locked" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read locked: {}", e))
+                )?;
+                
+                upload.locked = value.trim().parse().map_err(|_| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid locked: expected true or false, got '{}'", value))
+                )?;
+            },
+            "reproducible" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read reproducible: {}", e))
+                )?;
+                
+                upload.reproducible = value.trim().parse().map_err(|_| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid reproducible: expected true or false, got '{}'", value))
+                )?;
+            },
+            "profile" | "bin" | "example" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", file_name, e))
+                )?;
+                
+                let value = cargo_name(&file_name, value.trim(), &['-', '_'])?;
+                match file_name.as_str() {
+                    "profile" => upload.profile = Some(value),
+                    "bin" => upload.bin = Some(value),
+                    _ => upload.example = Some(value),
+                }
+            },
+            "features" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read features: {}", e))
+                )?;
+                
+                // Space or comma separated, like cargo's --features
+                upload.features = value.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|feature| !feature.is_empty())
+                    .map(|feature| cargo_name("feature", feature, &['-', '_', '+', '.', '/']))
+                    .collect::<Result<_, _>>()?;
+            },
+            "no_default_features" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read no_default_features: {}", e))
+                )?;
+                
+                upload.no_default_features = value.trim().parse().map_err(|_| 
+                    (StatusCode::BAD_REQUEST, format!("Invalid no_default_features: expected true or false, got '{}'", value))
+                )?;
+            },
+            "toolchain" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read toolchain: {}", e))
+                )?;
+                
+                let value = value.trim().to_string();
+                toolchain::validate_name(&value).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                upload.toolchain = Some(value);
+            },
+            "source_sha256" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read source_sha256: {}", e))
+                )?;
+                
+                upload.source_sha256 = Some(artifacts::parse_digest(value.trim()).map_err(|e| (StatusCode::BAD_REQUEST, e))?);
+            },
+            "user_id" | "project_id" => {
+                let value = field.text().await.map_err(|e| 
+                    (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", file_name, e))
+                )?;
+                
+                if file_name == "user_id" {
+                    upload.user_id = Some(value.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?);
+                } else {
+                    upload.project_id = Some(value.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?);
+                }
+            },
+            _ => {
+                println!("Unknown field: {}", file_name);
+                return Err((StatusCode::BAD_REQUEST, format!("Unknown field: {}", file_name)).into());
+            }
         }
     }
     
-    if !has_tar_file {
-        println!("No tar file received!");
-        return Err((StatusCode::BAD_REQUEST, "Missing tar file".to_string()));
+    if let Some(request) = run_request {
+        if args_field {
+            return Err((StatusCode::BAD_REQUEST, "Pass either args or run_request, not both".to_string()).into());
+        }
+        upload.args = request.argv().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        println!("Args from run_request: {:?}", upload.args);
     }
     
-    println!("About to decompress and run project at: {}", project_dir);
-    
-    // Decompress the tar file
-    let tar_path = format!("{}/project.tar.gz", project_dir);
-    println!("Decompressing tar file: {}", tar_path);
-    let decompress_result = decompress_tar(&tar_path, &project_dir).await;
+    Ok(upload)
+}
+
+// Checks a profile, target or feature name before it is passed to cargo
+fn cargo_name(field: &str, value: &str, allowed: &[char]) -> Result<String, (StatusCode, String)> {
+    let valid = !value.is_empty()
+        && !value.starts_with('-')
+        && value.len() <= 128
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || allowed.contains(&c));
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err((StatusCode::BAD_REQUEST, format!("Invalid {} name '{}'", field, value)))
+    }
+}
+
+async fn prepare_project(
+    user_id: &UserId,
+    project_id: &ProjectId,
+    upload: ProjectUpload,
+) -> Result<PreparedProject, CompilerApiError> {
+    let project_dir = format!("./projects/{}/{}", user_id, project_id);
+    println!("Project directory: {}", project_dir);
     
-    match decompress_result {
-        Ok(_) => {
-            println!("Successfully decompressed tar file");
-        },
-        Err(e) => {
-            println!("Failed to decompress tar file: {}", e);
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to decompress tar file: {}", e)));
+    let mut build = BuildOptions::from_upload(&upload)?;
+    let staged = match upload.archive {
+        Some(staged) => staged,
+        None => {
+            println!("No tar file received!");
+            return Err((StatusCode::BAD_REQUEST, "Missing tar file".to_string()).into());
         }
-    }
+    };
+    let tarball_sha256 = staged.sha256;
     
-    // After decompression, ensure proper file structure
-    println!("Ensuring project structure...");
-    ensure_project_structure(&project_dir).await?;
-    println!("Project structure ensured");
+    // Replace the project directory with the extracted upload, once no
+    // earlier job of the project still needs its sources
+    let project_lock = PROJECT_LOCKS.lock(StdPath::new(&project_dir)).await;
+    println!("Installing project directory...");
+    staged.install(StdPath::new(&project_dir)).await?;
+    println!("Project directory installed successfully");
     
-    // Verify that Cargo.toml and src/main.rs exist after decompression
+    // Cargo projects, workspaces included, are built as uploaded and cargo
+    // metadata finds their targets. Only loose sources get arranged into a package.
     let cargo_toml_path = format!("{}/Cargo.toml", project_dir);
-    let main_rs_path = format!("{}/src/main.rs", project_dir);
+    if !StdPath::new(&cargo_toml_path).exists() {
+        println!("No Cargo.toml, ensuring project structure...");
+        ensure_project_structure(&project_dir).await?;
+        println!("Project structure ensured");
+    }
     
     if !StdPath::new(&cargo_toml_path).exists() {
         println!("Missing Cargo.toml file after decompression");
-        return Err((StatusCode::BAD_REQUEST, "Missing Cargo.toml file after decompression".to_string()));
+        return Err((StatusCode::BAD_REQUEST, "Missing Cargo.toml file after decompression; upload a Cargo project or a main.rs".to_string()).into());
+    }
+    
+    println!("All required files found, project ready to run");
+    
+    // The toolchain field wins over a rust-toolchain.toml in the upload
+    if let Some(path) = toolchain::toolchain_file(StdPath::new(&project_dir)).filter(|_| !build.toolchain_requested) {
+        let text = archive::read_no_follow(&path)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", path.display(), e)))?;
+        let channel = toolchain::channel_from_file(&text).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        let origin = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        build.toolchain = installed_toolchain(&channel, &origin)?;
+    }
+    println!("Building with {}", build.toolchain.version);
+    
+    if build.locked && !StdPath::new(&project_dir).join("Cargo.lock").exists() {
+        return Err(CompilerApiError::LockfileError {
+            problem: LockfileProblem::Missing,
+            message: "A Cargo.lock is required; generate it with `cargo generate-lockfile` and include it in the upload".to_string(),
+            diagnostics: Vec::new(),
+        });
+    }
+    
+    let manifests = project_manifests(StdPath::new(&project_dir))?;
+    check_dependency_policy(StdPath::new(&project_dir), &manifests).await?;
+    
+    // The artifact cache key; identical sources reuse the same binary
+    let hash_dir = project_dir.clone();
+    let source_sha256 = tokio::task::spawn_blocking(move || artifacts::source_hash(StdPath::new(&hash_dir)))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Hashing task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash project sources: {}", e)))?;
+    println!("Source hash: {}", hex::encode(source_sha256));
+    let lockfile_uploaded = StdPath::new(&project_dir).join("Cargo.lock").exists();
+    
+    Ok(PreparedProject {
+        project_dir,
+        _project_lock: project_lock,
+        tarball_sha256,
+        source_sha256,
+        lockfile_uploaded,
+        build,
+        args: upload.args,
+        limits: SERVICE_LIMITS.tightened(&upload.limits),
+        input: upload.input,
+    })
+}
+
+// Unpacks the upload and queues it as a job
+async fn submit_project(
+    user_id: &UserId,
+    project_id: &ProjectId,
+    upload: ProjectUpload,
+) -> Result<Arc<Job>, CompilerApiError> {
+    let prepared = prepare_project(user_id, project_id, upload).await?;
+    Ok(JOB_MANAGER.submit(user_id.as_str(), project_id.as_str(), move |job| execute_project(job, prepared)))
+}
+
+// Runs one build step under the job, failing on a timeout
+async fn run_build_step(
+    job: &Job,
+    command: TokioCommand,
+    deadline: tokio::time::Instant,
+    limits: &ResourceLimits,
+) -> Result<ProcessOutput, CompilerApiError> {
+    let output = job.run_process(command, Phase::Build, deadline, limits, None).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build project: {}", e)))?;
+    if let Some(limit) = output.limit_exceeded {
+        println!("Project build hit limit: {:?}", limit);
+        return Err(CompilerApiError::LimitExceeded {
+            limit,
+            message: limit.describe(limits),
+        });
     }
+    Ok(output)
+}
+
+// Fetches the dependencies, checks the build policy and compiles the project
+// in the build sandbox
+async fn build_project(
+    job: &Job,
+    project_dir: &StdPath,
+    options: &BuildOptions,
+    limits: &ResourceLimits,
+) -> Result<BuiltBinary, CompilerApiError> {
+    let deadline = tokio::time::Instant::now() + limits.build_timeout();
+    let cargo_args = options.cargo_args();
+    let cargo_env = options.cargo_env();
     
-    if !StdPath::new(&main_rs_path).exists() {
-        println!("Missing src/main.rs file after decompression");
-        return Err((StatusCode::BAD_REQUEST, "Missing src/main.rs file after decompression".to_string()));
+    let mirror_args = match REGISTRY_MIRROR.as_ref() {
+        // Dependencies only ever come from the preloaded mirror
+        Some(mirror) => {
+            check_mirror(mirror, project_dir).await?;
+            mirror.cargo_args()
+        }
+        None => {
+            // Fetching needs the network but runs nothing from the upload
+            let mut fetch = TokioCommand::new("./runner.sh");
+            fetch.arg("fetch").arg(project_dir);
+            host_cargo(&mut fetch).args(&cargo_args).envs(cargo_env.clone());
+            let fetched = run_build_step(job, fetch, deadline, limits).await?;
+            if !fetched.status.success() {
+                return Err(cargo_failure("Failed to fetch dependencies", &fetched.stderr, Vec::new()));
+            }
+            Vec::new()
+        }
+    };
+    
+    let mut metadata = TokioCommand::new("cargo");
+    metadata.arg("metadata");
+    host_cargo(&mut metadata)
+        .args(["--format-version", "1", "--offline", "--manifest-path"])
+        .arg(project_dir.join("Cargo.toml"))
+        .args(&cargo_args)
+        .args(options.feature_args())
+        .args(&mirror_args)
+        .envs(cargo_env.clone());
+    let metadata = run_build_step(job, metadata, deadline, limits).await?;
+    if !metadata.status.success() {
+        return Err(cargo_failure("Failed to read project metadata", &metadata.stderr, Vec::new()));
     }
+    let metadata = Metadata::parse(&metadata.stdout)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    // Fetching or reading metadata writes a Cargo.lock if there was none and
+    // updates an uploaded one that doesn't match the manifests, so the crates
+    // cargo actually resolved are checked again
+    check_dependency_policy(project_dir, &project_manifests(project_dir)?).await?;
     
-    println!("All required files found, preparing to run project");
+    let violations = BUILD_POLICY.violations(&metadata);
+    if !violations.is_empty() {
+        println!("Project rejected by build policy: {:?}", violations);
+        return Err(CompilerApiError::PolicyViolation {
+            message: format!("Build policy rejected the project: {}", violations.join("; ")),
+            violations,
+        });
+    }
     
-    // Prepare the command to run the project
-    let mut command = TokioCommand::new("./runner.sh");
-    command.arg("run").arg(&project_dir);
+    let requested = options.target.as_ref().map(|(kind, name)| (*kind, name.as_str()));
+    let target = metadata.select_target(requested)
+        .map_err(|message| CompilerApiError::InvalidTarget { message, available: metadata.runnable_targets() })?;
+    println!("Building {} {} of {} with profile {}", target.kind.as_str(), target.name, target.package, options.profile);
     
-    // Add arguments if provided
-    for arg in &args {
-        command.arg(arg);
+    // Projects share compiled registry dependencies: the build starts from a
+    // copy of the shared target directory, and only the artifacts of registry
+    // crates are copied back. Builds that run any build script or proc macro,
+    // their own or a dependency's, could tamper with what they compile and
+    // neither take nor give anything.
+    let shared_cache = (DEPENDENCY_CACHE.enabled && !metadata.has_build_code())
+        .then(|| DEPENDENCY_CACHE.target_dir(&options.toolchain.verbose_version, &options.cache_profile()));
+    let target_dir = project_dir.join("target");
+    if shared_cache.is_some() && target_dir.exists() {
+        // Nothing the upload brought along may end up in the shared cache
+        fs::remove_dir_all(&target_dir)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to clear target directory: {}", e)))?;
+    }
+    fs::create_dir_all(&target_dir)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create target directory: {}", e)))?;
+    if let Some(cache_dir) = &shared_cache {
+        let target = target_dir.clone();
+        with_dependency_cache(cache_dir.clone(), deadline, move |cache| DependencyCache::seed(cache, &target)).await;
     }
     
-    command.current_dir("."); // Set working directory to current directory
+    // Build scripts and proc macros run offline in the sandbox, which only
+    // sees the toolchain and sources read-only and can only write to the
+    // target directory
+    let mut mounts = sandbox::toolchain_mounts();
+    mounts.push(BindMount { source: project_dir.to_path_buf(), target: project_dir.to_path_buf(), writable: false });
+    mounts.push(BindMount { source: target_dir.clone(), target: target_dir.clone(), writable: true });
+    if let Some(mirror) = REGISTRY_MIRROR.as_ref() {
+        mounts.push(BindMount { source: mirror.path.clone(), target: mirror.path.clone(), writable: false });
+    }
     
-    println!("Executing command: ./runner.sh run {} {} from directory: {:?}", 
-             project_dir, 
-             args.join(" "), 
-             std::env::current_dir().unwrap());
-    let output = command.output().await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to execute project: {}", e)))?;
+    let mut compile = tee_exec_command(SANDBOX_POLICY.exec_args(&mounts, false))?;
+    compile.env_clear().envs(sandbox::build_env());
+    compile.arg("./runner.sh").arg("compile").arg(project_dir).arg("--target-dir").arg(&target_dir)
+        .args(&cargo_args)
+        .args(options.compile_args(&target))
+        .args(&mirror_args)
+        .envs(cargo_env);
+    if options.reproducible {
+        compile.envs(BuildPaths {
+            project_dir: project_dir.to_path_buf(),
+            target_dir: target_dir.clone(),
+            cargo_home: reproducible::cargo_home(),
+            mirror: REGISTRY_MIRROR.as_ref().map(|mirror| mirror.path.clone()),
+        }.cargo_env(&options.profile));
+    }
+    let compiled = run_build_step(job, compile, deadline, limits).await?;
+    if !compiled.status.success() {
+        return Err(compile_failure(&compiled));
+    }
+    
+    let path = built_executable(&compiled.stdout, &target)
+        .ok_or_else(|| CompilerApiError::ExecutionError { message: "Build produced no binary".to_string(), diagnostics: Vec::new() })?;
+    let lockfile_sha256 = tokio::fs::read(project_dir.join("Cargo.lock")).await
+        .ok()
+        .map(|lockfile| sha256_hex(&lockfile));
+    if let Some(cache_dir) = shared_cache {
+        let (registry, local) = metadata.crate_names();
+        let shared = SharedCrates::new(registry, local);
+        with_dependency_cache(cache_dir, deadline, move |cache| DependencyCache::publish(&target_dir, cache, &shared)).await;
+    }
+    Ok(BuiltBinary { path, lockfile_sha256 })
+}
 
-    if output.status.success() {
-        let binary_output = String::from_utf8_lossy(&output.stdout).to_string();
+// Runs a copy between a build's target directory and the shared one under the
+// shared directory's lock. A failure only costs the cache, so it is logged.
+async fn with_dependency_cache(
+    cache_dir: PathBuf,
+    deadline: tokio::time::Instant,
+    copy: impl FnOnce(&StdPath) -> std::io::Result<()> + Send + 'static,
+) {
+    println!("Waiting for shared target directory {}", cache_dir.display());
+    let task = tokio::task::spawn_blocking(move || {
+        let _lock = DependencyCache::lock(&cache_dir)?;
+        copy(&cache_dir)
+    });
+    match tokio::time::timeout_at(deadline, task).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => println!("WARNING: Failed to update shared target directory: {}", e),
+        Ok(Err(e)) => println!("WARNING: Shared target directory task failed: {}", e),
+        Err(_) => println!("WARNING: Timed out waiting for the shared target directory"),
+    }
+}
+
+// Error for a failed cargo command, telling a stale lockfile apart
+fn cargo_failure(context: &str, stderr: &str, diagnostics: Vec<Diagnostic>) -> CompilerApiError {
+    if lockfile::is_stale_error(stderr) {
+        CompilerApiError::LockfileError {
+            problem: LockfileProblem::Stale,
+            message: format!("Cargo.lock is out of date with Cargo.toml: {}", stderr.trim()),
+            diagnostics,
+        }
+    } else {
+        CompilerApiError::ExecutionError { message: format!("{}: {}", context, stderr), diagnostics }
+    }
+}
+
+// Error for a failed compile, carrying rustc's diagnostics. Without an error
+// among them, cargo itself failed, e.g. on a stale lockfile, and the warnings
+// rustc gave before are attached to that failure.
+fn compile_failure(compiled: &ProcessOutput) -> CompilerApiError {
+    let diagnostics = diagnostics::parse(&compiled.stdout);
+    if !diagnostics.iter().any(|diagnostic| diagnostic.level.is_error()) {
+        return cargo_failure("Build failed", &compiled.stderr, diagnostics);
+    }
+    let errors: Vec<&str> = diagnostics.iter()
+        .filter(|diagnostic| diagnostic.level.is_error())
+        .map(|diagnostic| diagnostic.rendered.as_str())
+        .collect();
+    CompilerApiError::CompileError {
+        message: format!("Build failed: {}", errors.concat()),
+        diagnostics,
+    }
+}
+
+// Installed toolchain for a name from the request or a toolchain file
+fn installed_toolchain(requested: &str, origin: &str) -> Result<Toolchain, CompilerApiError> {
+    TOOLCHAINS.find(requested).cloned().ok_or_else(|| {
+        let installed = TOOLCHAINS.names();
+        CompilerApiError::ToolchainUnavailable {
+            requested: requested.to_string(),
+            message: format!("Toolchain '{}' from {} is not installed; available: {}", requested, origin, installed.join(", ")),
+            installed,
+        }
+    })
+}
+
+// Every Cargo.toml of the upload, the root one first, so the manifests of
+// workspace members and local path crates are checked as well
+fn project_manifests(project_dir: &StdPath) -> Result<Vec<PathBuf>, CompilerApiError> {
+    fn walk(dir: &StdPath, top: bool, manifests: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() && !(top && name == "target") && !name.to_string_lossy().starts_with('.') {
+                walk(&entry.path(), false, manifests)?;
+            } else if file_type.is_file() && name == "Cargo.toml" && !top {
+                manifests.push(entry.path());
+            }
+        }
+        Ok(())
+    }
+    
+    let mut manifests = vec![project_dir.join("Cargo.toml")];
+    walk(project_dir, true, &mut manifests)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list project manifests: {}", e)))?;
+    Ok(manifests)
+}
+
+// Applies the operator's dependency policy to the given manifests and to
+// the project's Cargo.lock
+async fn check_dependency_policy(project_dir: &StdPath, manifest_paths: &[PathBuf]) -> Result<(), CompilerApiError> {
+    let mut manifests = Vec::new();
+    for path in manifest_paths {
+        let manifest = archive::read_no_follow(path)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", path.display(), e)))?;
+        manifests.push(manifest.parse::<toml::Value>()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid {}: {}", path.display(), e)))?);
+    }
+    
+    let lock_path = project_dir.join("Cargo.lock");
+    let locked = if lock_path.exists() {
+        let text = archive::read_no_follow(&lock_path)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read Cargo.lock: {}", e)))?;
+        Some(lockfile::parse(&text).map_err(|e| (StatusCode::BAD_REQUEST, e))?)
+    } else {
+        None
+    };
+    
+    let violations = DEPENDENCY_POLICY.violations(&manifests, locked.as_deref());
+    if violations.is_empty() {
+        return Ok(());
+    }
+    println!("Project rejected by dependency policy: {:?}", violations);
+    Err(CompilerApiError::PolicyViolation {
+        message: format!("Dependency policy rejected the project: {}", violations.join("; ")),
+        violations,
+    })
+}
+
+// Fails with the list of locked crates the registry mirror does not have.
+// Without a Cargo.lock, cargo resolves against the mirror on its own.
+async fn check_mirror(mirror: &RegistryMirror, project_dir: &StdPath) -> Result<(), CompilerApiError> {
+    let lock_path = project_dir.join("Cargo.lock");
+    if !lock_path.exists() {
+        return Ok(());
+    }
+    let text = archive::read_no_follow(&lock_path)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read Cargo.lock: {}", e)))?;
+    let packages = lockfile::parse(&text).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    
+    let mirror = mirror.clone();
+    let missing = tokio::task::spawn_blocking(move || mirror.missing(&packages))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Mirror check failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    
+    let names: Vec<String> = missing.iter().map(|dep| format!("{} {}", dep.name, dep.version)).collect();
+    println!("Dependencies missing from the registry mirror: {:?}", names);
+    Err(CompilerApiError::MissingDependencies {
+        message: format!("Cargo.lock needs crates that are not in the registry mirror: {}", names.join(", ")),
+        missing,
+    })
+}
+
+// A freshly compiled binary in the project's target directory
+struct BuiltBinary {
+    path: PathBuf,
+    lockfile_sha256: Option<String>,
+}
+
+// Path of the target's executable, from cargo's JSON artifact messages
+fn built_executable(cargo_stdout: &str, target: &RunnableTarget) -> Option<PathBuf> {
+    cargo_stdout.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter(|message| message["target"]["name"] == target.name.as_str())
+        .filter(|message| message["target"]["kind"].as_array().is_some_and(|kinds| kinds.iter().any(|kind| kind == target.kind.as_str())))
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .next_back()
+}
+
+// Returns the cached binary for the project's sources, building and caching
+// it first on a miss. The flag tells whether cargo was skipped.
+async fn build_or_reuse(job: &Job, project: &PreparedProject) -> Result<(CachedBuild, bool), CompilerApiError> {
+    let key = artifacts::variant_key(&project.source_sha256, &project.build.variant());
+    if let Some(cached) = ARTIFACT_CACHE.lookup(&key, Some(&project.build.toolchain.version)) {
+        // Same sources, so the same Cargo.lock was uploaded; if cargo had to
+        // change it for that build, --locked would have refused
+        if project.build.locked {
+            let uploaded = tokio::fs::read(StdPath::new(&project.project_dir).join("Cargo.lock")).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read Cargo.lock: {}", e)))?;
+            if cached.info.lockfile_sha256.as_deref() != Some(sha256_hex(&uploaded).as_str()) {
+                return Err(CompilerApiError::LockfileError {
+                    problem: LockfileProblem::Stale,
+                    message: "Cargo.lock is out of date with Cargo.toml and would need to be updated".to_string(),
+                    diagnostics: Vec::new(),
+                });
+            }
+        }
+        println!("Reusing cached build {}", cached.info.source_sha256);
+        return Ok((cached, true));
+    }
+    
+    let project_dir = fs::canonicalize(&project.project_dir)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resolve project directory: {}", e)))?;
+    let built = build_project(job, &project_dir, &project.build, &project.limits).await?;
+    let binary = built.path.clone();
+    
+    // Only cache the binary under the key of the sources it was built from
+    let hash_dir = project_dir.clone();
+    let skip: &'static [&'static str] = if project.lockfile_uploaded { &[] } else { &["Cargo.lock"] };
+    let built_sha256 = tokio::task::spawn_blocking(move || artifacts::source_hash_skipping(&hash_dir, skip))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Hashing task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash project sources: {}", e)))?;
+    if built_sha256 != project.source_sha256 {
+        return Err(CompilerApiError::InternalError { message: "Project sources changed during the build".to_string() });
+    }
+    
+    let mut info = BuildInfo {
+        source_sha256: hex::encode(project.source_sha256),
+        binary_name: binary.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        toolchain: project.build.toolchain.version.clone(),
+        built_at: chrono::Utc::now().to_rfc3339(),
+        lockfile_sha256: built.lockfile_sha256.clone(),
+        binary_sha256: String::new(),
+        reproducible: project.build.reproducible,
+        rustc_version: project.build.toolchain.verbose_version.clone(),
+    };
+    let cached = tokio::task::spawn_blocking(move || {
+        info.binary_sha256 = hex::encode(artifacts::file_sha256(&binary)?);
+        ARTIFACT_CACHE.store(&key, &binary, info)
+    })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Cache task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to cache build: {}", e)))?;
+    drop(built);
+    println!("Cached build {}", cached.info.source_sha256);
+    Ok((cached, false))
+}
+
+// A built binary and the inputs of one execution of it
+struct ExecutionPlan {
+    build: CachedBuild,
+    cached_build: bool,
+    // Code the receipt and quote commit to
+    code: ExecutedCode,
+    workdir: PathBuf,
+    args: Vec<String>,
+    limits: ResourceLimits,
+    input: ProgramInput,
+}
+
+// Job body of /build: compiles the project, or finds it in the cache
+async fn build_only_project(job: Arc<Job>, project: PreparedProject) -> JobResult {
+    let build_started = std::time::Instant::now();
+    let (build, cached_build) = build_or_reuse(&job, &project).await.map_err(|error| JobError {
+        report: RunReport {
+            outcome: Outcome::of_build_error(&error),
+            build_log: job.log_text(LogStream::Build),
+            build_duration_ms: build_started.elapsed().as_millis() as u64,
+            ..RunReport::default()
+        },
+        error,
+    })?;
+    Ok(JobResponse::Build(BuildResponse {
+        status: "success".to_string(),
+        source_sha256: build.info.source_sha256,
+        cached_build,
+        toolchain: build.info.toolchain,
+        lockfile_sha256: build.info.lockfile_sha256,
+        binary_sha256: build.info.reproducible.then_some(build.info.binary_sha256),
+        rustc_version: build.info.rustc_version,
+    }))
+}
+
+// Job body of /run: builds (or reuses) the project and runs it
+async fn execute_project(job: Arc<Job>, project: PreparedProject) -> JobResult {
+    let started_at = chrono::Utc::now();
+    let build_started = std::time::Instant::now();
+    let built = build_or_reuse(&job, &project).await;
+    let mut report = RunReport {
+        build_log: job.log_text(LogStream::Build),
+        build_duration_ms: build_started.elapsed().as_millis() as u64,
+        ..RunReport::default()
+    };
+    let (build, cached_build) = match built {
+        Ok(built) => built,
+        Err(error) => {
+            report.outcome = Outcome::of_build_error(&error);
+            return Err(JobError { error, report });
+        }
+    };
+    let workdir = fs::canonicalize(&project.project_dir)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resolve project directory: {}", e)))?;
+    
+    run_binary(&job, ExecutionPlan {
+        build,
+        cached_build,
+        code: ExecutedCode::Tarball(project.tarball_sha256),
+        workdir,
+        args: project.args,
+        limits: project.limits,
+        input: project.input,
+    }, started_at, report).await
+}
+
+// Runs the program and reports what it produced, on failure as well
+async fn run_binary(job: &Job, plan: ExecutionPlan, started_at: chrono::DateTime<chrono::Utc>, mut report: RunReport) -> JobResult {
+    let result = run_program(job, plan, started_at, &mut report).await;
+    result.map_err(|error| JobError { error, report })
+}
+
+async fn run_program(
+    job: &Job,
+    plan: ExecutionPlan,
+    started_at: chrono::DateTime<chrono::Utc>,
+    report: &mut RunReport,
+) -> Result<JobResponse, CompilerApiError> {
+    let ExecutionPlan { build, cached_build, code, workdir, args, limits, input } = plan;
+    let binary = &build.binary;
+    
+    // tee-exec writes how the program ended and its peak RSS here
+    let stats_path = env::temp_dir().join(format!("tee-exec-stats-{}.json", uuid::Uuid::new_v4()));
+    
+    // tee-exec applies the rlimits and sandbox to the program only
+    let mut exec_args = limits.exec_args(RUN_CGROUPS.as_deref());
+    exec_args.push("--stats".to_string());
+    exec_args.push(stats_path.to_string_lossy().into_owned());
+    exec_args.extend(SANDBOX_POLICY.exec_args(&input.files.mounts(), SANDBOX_POLICY.allow_network));
+    let mut command = tee_exec_command(exec_args)?;
+    command.arg(binary).args(&args);
+    command.env_clear().envs(sandbox::program_env()).envs(&input.env);
+    // Without the sandbox nothing can be mounted, so the program runs in the
+    // directory holding its input files instead
+    let workdir = match input.files.dir() {
+        Some(dir) if !SANDBOX_POLICY.enabled => dir.to_path_buf(),
+        _ => workdir,
+    };
+    command.current_dir(&workdir);
+    
+    println!("Executing {} {} in {}", binary.display(), args.join(" "), workdir.display());
+    let deadline = tokio::time::Instant::now() + limits.run_timeout();
+    let run_started = std::time::Instant::now();
+    let output = job.run_process(command, Phase::Run, deadline, &limits, input.stdin.as_deref()).await;
+    report.run_duration_ms = run_started.elapsed().as_millis() as u64;
+    // Missing when tee-exec itself was killed, e.g. on a timeout
+    let stats = tokio::fs::read(&stats_path).await.ok()
+        .and_then(|stats| serde_json::from_slice::<ExitStats>(&stats).ok());
+    let _ = tokio::fs::remove_file(&stats_path).await;
+    let output = output
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to execute project: {}", e)))?;
+    let finished_at = chrono::Utc::now();
+    
+    report.stdout = output.stdout.clone();
+    report.stderr = output.stderr.clone();
+    match stats {
+        Some(stats) => {
+            report.exit_code = stats.exit_code;
+            report.signal = stats.signal;
+            report.peak_rss_bytes = Some(stats.peak_rss_bytes);
+        }
+        None => {
+            report.exit_code = output.status.code();
+            report.signal = output.status.signal();
+        }
+    }
+    
+    // Limits as the job supervisor or tee-exec saw them fire
+    let limit_exceeded = output.limit_exceeded
+        .or_else(|| stats.and_then(|stats| limits.enforced_limit(&stats)));
+    if let Some(limit) = limit_exceeded {
+        println!("Project execution hit limit: {:?}", limit);
+        report.outcome = Some(Outcome::of_run(report.exit_code, report.signal, &output.stderr, Some(limit)));
+        return Err(CompilerApiError::LimitExceeded {
+            limit,
+            message: limit.describe(&limits),
+        });
+    }
+    
+    let outcome = Outcome::of_run(report.exit_code, report.signal, &output.stderr, None);
+    report.outcome = Some(outcome.clone());
+    if outcome == Outcome::Success {
+        let binary_output = output.stdout;
         println!("Project executed successfully");
         println!("Raw output: {}", binary_output);
         
@@ -207,45 +1234,440 @@ pub async fn run_project(
         let result = binary_output.trim().to_string();
         println!("Result: {}", result);
         
-        Ok(Response::builder()
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_string(&ExecutionResponse {
-                status: "success".to_string(),
-                output: result,
-                quote: String::new(),
-            }).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))?)
+        // Bind the code, the args, the other inputs and stdout exactly as
+        // the receipt records them into the attestation quote
+        let stdin_sha256 = input.stdin.as_deref().map(sha256_hex);
+        let inputs = ExecutionInputs {
+            stdin_sha256: stdin_sha256.as_deref(),
+            env: &input.env,
+            input_files: &input.files.files,
+        };
+        let quote = generate_quote(execution_report_data_from_digest(&code, &args, &inputs, binary_output.as_bytes())).await?;
+        println!("Attestation quote generated ({} bytes)", quote.len());
+        
+        let receipt = RECEIPT_SIGNER.sign(ExecutionReceipt {
+            version: RECEIPT_VERSION,
+            tarball_sha256: match code { ExecutedCode::Tarball(sha256) => Some(hex::encode(sha256)), ExecutedCode::Source(_) => None },
+            source_sha256: match code { ExecutedCode::Source(sha256) => Some(hex::encode(sha256)), ExecutedCode::Tarball(_) => None },
+            args: args.clone(),
+            stdout: binary_output,
+            exit_code: output.status.code().unwrap_or(0),
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            toolchain: build.info.toolchain.clone(),
+            lockfile_sha256: build.info.lockfile_sha256.clone(),
+            binary_sha256: build.info.reproducible.then(|| build.info.binary_sha256.clone()),
+            stdin_sha256,
+            env: input.env.clone(),
+            input_files: input.files.files.clone(),
+        }).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to sign receipt: {}", e)))?;
+        
+        Ok(JobResponse::Execution(Box::new(ExecutionResponse {
+            status: "success".to_string(),
+            output: result,
+            quote: base64::engine::general_purpose::STANDARD.encode(&quote),
+            receipt,
+            source_sha256: build.info.source_sha256,
+            cached_build,
+            lockfile_sha256: build.info.lockfile_sha256,
+            binary_sha256: build.info.reproducible.then_some(build.info.binary_sha256),
+            rustc_version: build.info.rustc_version,
+            report: report.clone(),
+        })))
     } else {
-        let stderr_output = String::from_utf8_lossy(&output.stderr).to_string();
-        let exit_code = output.status.code().unwrap_or(-1);
-        println!("Project execution failed with exit code: {}", exit_code);
+        let stderr_output = output.stderr;
+        println!("Project execution failed: {:?}", outcome);
         
-        if exit_code == 101 || stderr_output.contains("panicked at") {
-            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Panic: {}", stderr_output)))
+        if let Outcome::Panic { .. } = outcome {
+            Err(CompilerApiError::PanicError { message: format!("Panic: {}", stderr_output) })
         } else {
-            Err((StatusCode::BAD_REQUEST, format!("Execution error: {}", stderr_output)))
+            Err(CompilerApiError::ExecutionError { message: format!("Execution error: {}", stderr_output), diagnostics: Vec::new() })
         }
     }
 }
 
-async fn decompress_tar(tar_path: &str, extract_dir: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Use tar command to decompress
-    let output = TokioCommand::new("tar")
-        .arg("-xzf")
-        .arg(tar_path)
-        .arg("-C")
-        .arg(extract_dir)
-        .output()
-        .await?;
+// Validates the ids taken from the URL path
+fn parse_ids(user_id: &str, project_id: &str) -> Result<(UserId, ProjectId), (StatusCode, String)> {
+    Ok((
+        user_id.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        project_id.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+    ))
+}
+
+// Synchronous wrapper over the job API: queues the project and waits for it
+pub async fn run_project(
+    Path((user_id, project_id)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Response<Body>, JobError> {
     
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Tar decompression failed: {}", error).into());
+    println!("=== run_project called for user: {}, project: {} ===", user_id, project_id);
+    let (user_id, project_id) = parse_ids(&user_id, &project_id)?;
+    
+    let upload = read_project_upload(&mut multipart).await?;
+    let job = submit_project(&user_id, &project_id, upload).await?;
+    wait_for_result(&job).await
+}
+
+// Waits for a job and answers with its result
+async fn wait_for_result(job: &Job) -> Result<Response<Body>, JobError> {
+    job.wait().await;
+    
+    let response = match job.result() {
+        Some(Ok(response)) => response,
+        Some(Err(failure)) => return Ok(failure.into_response()),
+        None => return Err(CompilerApiError::InternalError { message: "Job finished without a result".to_string() }.into()),
+    };
+    
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&response)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))?)
+}
+
+// Compiles a project without running it. The returned source_sha256 can be
+// passed to /execute to run the binary with different args.
+pub async fn build_only(
+    Path((user_id, project_id)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Response<Body>, JobError> {
+    println!("=== build_only called for user: {}, project: {} ===", user_id, project_id);
+    let (user_id, project_id) = parse_ids(&user_id, &project_id)?;
+    
+    let upload = read_project_upload(&mut multipart).await?;
+    if !upload.input.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "stdin, env and input files only apply to runs; pass them to /execute".to_string()).into());
+    }
+    let prepared = prepare_project(&user_id, &project_id, upload).await?;
+    let job = JOB_MANAGER.submit(user_id.as_str(), project_id.as_str(), move |job| build_only_project(job, prepared));
+    wait_for_result(&job).await
+}
+
+// Runs a binary from the artifact cache, skipping cargo entirely
+pub async fn execute_build(
+    Path((user_id, project_id)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Response<Body>, JobError> {
+    println!("=== execute_build called for user: {}, project: {} ===", user_id, project_id);
+    let (user_id, project_id) = parse_ids(&user_id, &project_id)?;
+    
+    let upload = read_project_upload(&mut multipart).await?;
+    if upload.archive.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "/execute takes a source_sha256, not a tar_file; use /run or /build".to_string()).into());
+    }
+    let source_sha256 = upload.source_sha256
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing source_sha256".to_string()))?;
+    // Entries are found by the same settings they were built with; without a
+    // toolchain field, whichever toolchain built the entry is fine
+    let options = BuildOptions::from_upload(&upload)?;
+    let toolchain = options.toolchain_requested.then_some(options.toolchain.version.as_str());
+    let build = ARTIFACT_CACHE.lookup(&artifacts::variant_key(&source_sha256, &options.variant()), toolchain)
+        .ok_or_else(|| CompilerApiError::BuildNotFound {
+            source_sha256: hex::encode(source_sha256),
+            message: format!("No cached build for source {}; build it with /build first", hex::encode(source_sha256)),
+        })?;
+    
+    // No tarball comes with the request and the entry may have been built from
+    // someone else's upload, so the receipt commits to the source hash instead
+    let code = ExecutedCode::Source(source_sha256);
+    // Without the sandbox the program can write to its working directory, so
+    // it gets an empty one of its own rather than the cache entry's
+    let scratch = env::temp_dir().join(format!("execute-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&scratch)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create working directory: {}", e)))?;
+    let plan = ExecutionPlan {
+        build,
+        cached_build: true,
+        code,
+        workdir: scratch.clone(),
+        args: upload.args,
+        limits: SERVICE_LIMITS.tightened(&upload.limits),
+        input: upload.input,
+    };
+    let job = JOB_MANAGER.submit(user_id.as_str(), project_id.as_str(), move |job| async move {
+        let result = run_binary(&job, plan, chrono::Utc::now(), RunReport::default()).await;
+        let _ = tokio::fs::remove_dir_all(&scratch).await;
+        result
+    });
+    wait_for_result(&job).await
+}
+
+// JSON variant of /run for a single main.rs. The source is staged as a loose
+// upload, so it is arranged into a package like a tarball without a manifest.
+// Receipts record the SHA-256 of the request body as tarball_sha256.
+pub async fn run_source(
+    Path((user_id, project_id)): Path<(String, String)>,
+    body: axum::body::Bytes,
+) -> Result<Response<Body>, JobError> {
+    println!("=== run_source called for user: {}, project: {} ===", user_id, project_id);
+    let (user_id, project_id) = parse_ids(&user_id, &project_id)?;
+    
+    let submission: SourceSubmission = serde_json::from_slice(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid source submission: {}", e)))?;
+    let source = match &submission.dependencies {
+        Some(dependencies) => script::with_dependencies(&submission.source, dependencies),
+        None => script::parse(&submission.source).map(|_| submission.source.clone()),
+    }
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    
+    if let Some(toolchain) = &submission.toolchain {
+        toolchain::validate_name(toolchain).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+    let profile = submission.profile.as_deref()
+        .map(|profile| cargo_name("profile", profile, &['-', '_']))
+        .transpose()?;
+    let features = submission.features.iter()
+        .map(|feature| cargo_name("feature", feature, &['-', '_', '+', '.', '/']))
+        .collect::<Result<_, _>>()?;
+    
+    let args = if submission.params.is_empty() {
+        submission.args
+    } else if submission.args.is_empty() {
+        RunRequest { params: submission.params, argv: None }.argv().map_err(|e| (StatusCode::BAD_REQUEST, e))?
+    } else {
+        return Err((StatusCode::BAD_REQUEST, "Pass either args or params, not both".to_string()).into());
+    };
+    
+    let mut input = ProgramInput::default();
+    if let Some(stdin) = submission.stdin {
+        input.set_stdin(stdin.into_bytes())?;
+    }
+    input.set_env(submission.env)?;
+    for (name, contents) in &submission.files {
+        input.files.add(name, contents.as_bytes(), &EXTRACT_LIMITS).await?;
+    }
+    
+    let sha256: [u8; 32] = Sha256::digest(&body).into();
+    let staged = upload::stage_source("main.rs", &source, sha256).await?;
+    let upload = ProjectUpload {
+        user_id: None,
+        project_id: None,
+        archive: Some(staged),
+        source_sha256: None,
+        args,
+        limits: submission.limits,
+        locked: false,
+        reproducible: false,
+        toolchain: submission.toolchain,
+        profile,
+        features,
+        no_default_features: false,
+        bin: None,
+        example: None,
+        input,
+    };
+    let job = submit_project(&user_id, &project_id, upload).await?;
+    wait_for_result(&job).await
+}
+
+// Streaming variant of /run: queues the project and forwards its output as
+// Server-Sent Events while it builds and runs
+pub async fn run_project_stream(
+    Path((user_id, project_id)): Path<(String, String)>,
+    mut multipart: Multipart,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>>, JobError> {
+    println!("=== run_project_stream called for user: {}, project: {} ===", user_id, project_id);
+    let (user_id, project_id) = parse_ids(&user_id, &project_id)?;
+    
+    let upload = read_project_upload(&mut multipart).await?;
+    let job = submit_project(&user_id, &project_id, upload).await?;
+    Ok(job_event_stream(&job))
+}
+
+pub async fn stream_job_events(
+    Path(job_id): Path<String>,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let job = JOB_MANAGER.get(&job_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Job not found: {}", job_id)))?;
+    Ok(job_event_stream(&job))
+}
+
+fn log_event(line: &LogLine) -> Event {
+    // Event name is the stream (build, stdout or stderr), data is the raw line
+    Event::default().event(line.stream.as_str()).data(&line.text)
+}
+
+fn exit_event(exit: &ExitEvent) -> Event {
+    Event::default().event("exit").json_data(exit)
+        .unwrap_or_else(|_| Event::default().event("exit"))
+}
+
+// Replays the job's logs so far, then follows it live until the exit event
+fn job_event_stream(job: &Job) -> Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>> {
+    let job_id = job.id.clone();
+    let (backlog, finished, mut events) = job.subscribe();
+    
+    let stream = async_stream::stream! {
+        yield Ok(Event::default().event("job").data(&job_id));
+        for line in &backlog {
+            yield Ok(log_event(line));
+        }
+        if let Some(exit) = finished {
+            yield Ok(exit_event(&exit));
+            return;
+        }
+        loop {
+            match events.recv().await {
+                Ok(JobEvent::Log(line)) => yield Ok(log_event(&line)),
+                Ok(JobEvent::Finished(exit)) => {
+                    yield Ok(exit_event(&exit));
+                    break;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    yield Ok(Event::default().event("lagged").data(skipped.to_string()));
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+    
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn create_job(
+    mut multipart: Multipart,
+) -> Result<Response<Body>, JobError> {
+    println!("=== create_job called ===");
+    
+    let upload = read_project_upload(&mut multipart).await?;
+    let user_id = upload.user_id.clone()
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing user_id".to_string()))?;
+    let project_id = upload.project_id.clone()
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing project_id".to_string()))?;
+    
+    let job = submit_project(&user_id, &project_id, upload).await?;
+    
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&JobCreatedResponse {
+            job_id: job.id.clone(),
+            state: job.state(),
+        }).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))?)
+}
+
+fn job_status(job: &Job) -> JobStatusResponse {
+    let (result, error) = match job.result() {
+        Some(Ok(response)) => (Some(response), None),
+        Some(Err(error)) => (None, Some(error)),
+        None => (None, None),
+    };
+    
+    JobStatusResponse {
+        job_id: job.id.clone(),
+        user_id: job.user_id.clone(),
+        project_id: job.project_id.clone(),
+        state: job.state(),
+        created_at: job.created_at.to_rfc3339(),
+        logs: job.logs(),
+        result,
+        error,
+    }
+}
+
+pub async fn get_job(
+    Path(job_id): Path<String>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let job = JOB_MANAGER.get(&job_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Job not found: {}", job_id)))?;
+    
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&job_status(&job))
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))
+}
+
+pub async fn cancel_job(
+    Path(job_id): Path<String>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    println!("=== cancel_job called for job: {} ===", job_id);
+    
+    let job = JOB_MANAGER.get(&job_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Job not found: {}", job_id)))?;
+    
+    if !job.cancel() {
+        return Err((StatusCode::CONFLICT, format!("Job {} has already finished", job_id)));
     }
+    job.wait().await;
     
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&job_status(&job))
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))
+}
+
+// Starts a trivial program in the sandbox, so a host that doesn't allow its
+// namespaces fails at startup rather than on every build
+fn check_sandbox() -> Result<(), String> {
+    let tee_exec = env::current_exe()
+        .map_err(|e| format!("Failed to locate tee-exec: {}", e))?
+        .with_file_name("tee-exec");
+    let output = std::process::Command::new(tee_exec)
+        .args(SANDBOX_POLICY.exec_args(&[], false))
+        .args(["--", "/bin/true"])
+        .output()
+        .map_err(|e| format!("Failed to start tee-exec: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "The sandbox does not work on this host ({}); run the container with --security-opt seccomp=unconfined \
+            and apparmor=unconfined, or set SANDBOX=off",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
     Ok(())
 }
 
+// tee-exec with the given options, ready for the program and its args
+fn tee_exec_command(exec_args: Vec<String>) -> Result<TokioCommand, (StatusCode, String)> {
+    let tee_exec = env::current_exe()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to locate tee-exec: {}", e)))?
+        .with_file_name("tee-exec");
+    
+    let mut command = TokioCommand::new(tee_exec);
+    command.args(exec_args).arg("--");
+    Ok(command)
+}
+
+// Requests a quote for the given report data and checks that it echoes it back
+async fn generate_quote(report_data: [u8; attestation::REPORT_DATA_LEN]) -> Result<Vec<u8>, CompilerApiError> {
+    let provider = ATTESTATION_PROVIDER.clone()
+        .map_err(|e| CompilerApiError::AttestationUnavailable { message: format!("Attestation is unavailable: {}", e) })?;
+    let quote = tokio::task::spawn_blocking(move || provider.get_quote(&report_data))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Attestation task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate attestation quote: {}", e)))?;
+    
+    let parsed_quote = TdxQuote::parse(&quote)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Invalid attestation quote: {}", e)))?;
+    if parsed_quote.report_data != report_data {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Attestation quote does not carry the requested report data".to_string()).into());
+    }
+    
+    Ok(quote)
+}
+
+pub async fn get_attestation_pubkey() -> Result<Response<Body>, CompilerApiError> {
+    println!("=== get_attestation_pubkey called ===");
+    
+    let public_key = RECEIPT_SIGNER.public_key();
+    let quote = generate_quote(receipt_key_report_data(&public_key)).await?;
+    
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&AttestationKeyResponse {
+            algorithm: "ed25519".to_string(),
+            public_key: base64::engine::general_purpose::STANDARD.encode(public_key),
+            quote: base64::engine::general_purpose::STANDARD.encode(&quote),
+        }).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize response: {}", e)))?))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build response: {}", e)))?)
+}
+
+// Arranges an upload without a manifest into a package: .rs files at the top
+// level move into src/, and a main.rs there gets a generated Cargo.toml,
+// taken from the manifest embedded in it if it has one
 async fn ensure_project_structure(project_dir: &str) -> Result<(), (StatusCode, String)> {
     // Ensure src directory exists
     let src_dir = format!("{}/src", project_dir);
@@ -276,6 +1698,21 @@ async fn ensure_project_structure(project_dir: &str) -> Result<(), (StatusCode,
             }
         }
     }
+    
+    let main_rs = StdPath::new(&src_dir).join("main.rs");
+    if main_rs.exists() {
+        let source = archive::read_no_follow(&main_rs)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read main.rs: {}", e)))?;
+        let script = script::parse(&source).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        let manifest = script::manifest(script.manifest.as_ref()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if script.body != source {
+            fs::write(&main_rs, &script.body)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write main.rs: {}", e)))?;
+        }
+        fs::write(format!("{}/Cargo.toml", project_dir), manifest)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write Cargo.toml: {}", e)))?;
+        println!("Generated Cargo.toml");
+    }
    
     Ok(())
 }
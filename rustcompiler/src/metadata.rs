@@ -0,0 +1,278 @@
+// Subset of `cargo metadata --format-version 1` used by the build checks
+//
+// Only manifests are read to produce it, so it is safe to compute on the host
+// before anything from the upload is compiled.
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub resolve: Option<Resolve>,
+    pub target_directory: String,
+    pub workspace_root: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub targets: Vec<Target>,
+    pub manifest_path: String,
+    // `default-run` from Cargo.toml
+    #[serde(default)]
+    pub default_run: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub src_path: String,
+}
+
+// Kinds of target that build into an executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnableKind {
+    Bin,
+    Example,
+}
+
+impl RunnableKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RunnableKind::Bin => "bin",
+            RunnableKind::Example => "example",
+        }
+    }
+
+    // Cargo flag selecting a target of this kind
+    pub fn cargo_flag(self) -> &'static str {
+        match self {
+            RunnableKind::Bin => "--bin",
+            RunnableKind::Example => "--example",
+        }
+    }
+}
+
+// An executable target of a workspace member
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RunnableTarget {
+    pub name: String,
+    pub kind: RunnableKind,
+    pub package: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DepKind {
+    // None for normal dependencies, otherwise "dev" or "build"
+    pub kind: Option<String>,
+}
+
+impl Target {
+    pub fn is_build_script(&self) -> bool {
+        self.kind.iter().any(|kind| kind == "custom-build")
+    }
+
+    pub fn is_proc_macro(&self) -> bool {
+        self.kind.iter().any(|kind| kind == "proc-macro")
+    }
+
+    pub fn runnable_kind(&self) -> Option<RunnableKind> {
+        if self.kind.iter().any(|kind| kind == "bin") {
+            Some(RunnableKind::Bin)
+        } else if self.kind.iter().any(|kind| kind == "example") {
+            Some(RunnableKind::Example)
+        } else {
+            None
+        }
+    }
+}
+
+impl Metadata {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid cargo metadata: {}", e))
+    }
+
+    // Workspace members plus everything they pull in through normal and
+    // build dependencies. Dev-dependencies are not built for a release binary.
+    pub fn build_packages(&self) -> Vec<&Package> {
+        let Some(resolve) = &self.resolve else {
+            return self.packages.iter().collect();
+        };
+        let nodes: HashMap<&str, &Node> = resolve.nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut pending: Vec<&str> = self.workspace_members.iter().map(String::as_str).collect();
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            if let Some(node) = nodes.get(id) {
+                for dep in &node.deps {
+                    if dep.dep_kinds.iter().any(|k| k.kind.as_deref() != Some("dev")) {
+                        pending.push(&dep.pkg);
+                    }
+                }
+            }
+        }
+
+        self.packages.iter().filter(|p| seen.contains(p.id.as_str())).collect()
+    }
+
+    fn members(&self) -> impl Iterator<Item = &Package> {
+        self.packages.iter().filter(|p| self.workspace_members.contains(&p.id))
+    }
+
+    // Binaries and examples of the workspace members
+    pub fn runnable_targets(&self) -> Vec<RunnableTarget> {
+        self.members()
+            .flat_map(|package| package.targets.iter().filter_map(|target| {
+                target.runnable_kind().map(|kind| RunnableTarget {
+                    name: target.name.clone(),
+                    kind,
+                    package: package.name.clone(),
+                })
+            }))
+            .collect()
+    }
+
+    // The executable to build, the way `cargo run` picks it: the requested
+    // target, or else the only binary, or else the root package's
+    // `default-run`. Fails when the choice is unknown or ambiguous.
+    pub fn select_target(&self, requested: Option<(RunnableKind, &str)>) -> Result<RunnableTarget, String> {
+        let targets = self.runnable_targets();
+        if let Some((kind, name)) = requested {
+            return targets.into_iter()
+                .find(|target| target.kind == kind && target.name == name)
+                .ok_or_else(|| format!("The project has no {} target named '{}'", kind.as_str(), name));
+        }
+
+        let has_examples = targets.iter().any(|target| target.kind == RunnableKind::Example);
+        let bins: Vec<RunnableTarget> = targets.into_iter().filter(|target| target.kind == RunnableKind::Bin).collect();
+        if let [bin] = bins.as_slice() {
+            return Ok(bin.clone());
+        }
+        let root = self.resolve.as_ref().and_then(|resolve| resolve.root.as_deref());
+        let default_run = self.packages.iter()
+            .find(|package| Some(package.id.as_str()) == root)
+            .and_then(|package| package.default_run.as_deref());
+        if let Some(bin) = default_run.and_then(|name| bins.iter().find(|bin| bin.name == name)) {
+            return Ok(bin.clone());
+        }
+        if bins.is_empty() && has_examples {
+            Err("The project has no binary target; pick an example".to_string())
+        } else if bins.is_empty() {
+            Err("The project has no binary or example target to run".to_string())
+        } else {
+            Err("The project has several binary targets; pick one with `bin` or `example`".to_string())
+        }
+    }
+
+    // Package and target names of the build's registry packages, then those
+    // of its workspace, path and git packages
+    pub fn crate_names(&self) -> (Vec<&str>, Vec<&str>) {
+        let (registry, local): (Vec<&Package>, Vec<&Package>) = self.build_packages()
+            .into_iter()
+            .partition(|p| p.source.as_deref().is_some_and(|source| source.starts_with("registry+") || source.starts_with("sparse+")));
+        (package_names(registry), package_names(local))
+    }
+
+    // Whether any crate of the build, from the upload or a registry, runs
+    // code at compile time through a build script or proc macro. A registry
+    // crate's build script may read the project's sources or environment too.
+    pub fn has_build_code(&self) -> bool {
+        self.build_packages()
+            .iter()
+            .any(|p| p.targets.iter().any(|t| t.is_build_script() || t.is_proc_macro()))
+    }
+}
+
+fn package_names(packages: Vec<&Package>) -> Vec<&str> {
+    packages.into_iter()
+        .flat_map(|p| std::iter::once(p.name.as_str()).chain(p.targets.iter().map(|t| t.name.as_str())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(id: &str, source: Option<&str>, kind: &str) -> Package {
+        Package {
+            id: id.to_string(),
+            name: id.to_string(),
+            version: "1.0.0".to_string(),
+            source: source.map(str::to_string),
+            targets: vec![Target { name: id.to_string(), kind: vec![kind.to_string()], src_path: format!("{}/src/lib.rs", id) }],
+            manifest_path: format!("{}/Cargo.toml", id),
+            default_run: None,
+        }
+    }
+
+    // app depends on `dep` and, for tests only, on `dev`
+    fn metadata(dep_kind: &str, dev_kind: &str) -> Metadata {
+        let registry = Some("registry+https://github.com/rust-lang/crates.io-index");
+        let node = |id: &str, deps: Vec<NodeDep>| Node { id: id.to_string(), deps };
+        let dep = |pkg: &str, kind: Option<&str>| NodeDep {
+            pkg: pkg.to_string(),
+            dep_kinds: vec![DepKind { kind: kind.map(str::to_string) }],
+        };
+        Metadata {
+            packages: vec![package("app", None, "bin"), package("dep", registry, dep_kind), package("dev", registry, dev_kind)],
+            workspace_members: vec!["app".to_string()],
+            resolve: Some(Resolve {
+                nodes: vec![
+                    node("app", vec![dep("dep", None), dep("dev", Some("dev"))]),
+                    node("dep", Vec::new()),
+                    node("dev", Vec::new()),
+                ],
+                root: Some("app".to_string()),
+            }),
+            target_directory: "target".to_string(),
+            workspace_root: ".".to_string(),
+        }
+    }
+
+    #[test]
+    fn build_code_of_registry_dependencies_counts() {
+        assert!(!metadata("lib", "lib").has_build_code());
+        assert!(metadata("proc-macro", "lib").has_build_code());
+        assert!(metadata("custom-build", "lib").has_build_code());
+    }
+
+    #[test]
+    fn dev_dependencies_are_not_built() {
+        assert!(!metadata("lib", "proc-macro").has_build_code());
+    }
+
+    #[test]
+    fn splits_registry_crates_from_the_project() {
+        let metadata = metadata("lib", "lib");
+        let (registry, local) = metadata.crate_names();
+        assert_eq!(registry, ["dep", "dep"]);
+        assert_eq!(local, ["app", "app"]);
+    }
+}
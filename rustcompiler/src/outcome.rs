@@ -0,0 +1,218 @@
+// How a request ended, from compiling the project to running it
+//
+// The outcome is decided from what the build and the program actually did:
+// a compile error comes from cargo's diagnostics, a panic needs both the
+// exit status of a panicking process and the panic hook's report on stderr,
+// and limits are the ones the job supervisor or tee-exec enforced.
+
+use axum::http::StatusCode;
+use rust_tee_compiler::limits::LimitKind;
+
+use crate::types::CompilerApiError;
+
+// Exit code of a Rust program whose main thread panicked with panic=unwind
+const PANIC_EXIT_CODE: i32 = 101;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    CompileError,
+    Panic {
+        thread: String,
+        message: String,
+        // None for panic reports in a format we don't recognize
+        location: Option<PanicLocation>,
+    },
+    NonZeroExit { exit_code: i32 },
+    Signaled { signal: i32 },
+    // The build or the run hit its wall-clock timeout
+    TimedOut,
+    LimitExceeded { limit: LimitKind },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PanicLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Outcome {
+    // Status of the response. Program failures are not the service's fault;
+    // timeouts get a 422 as well, since clients may retry a 408 on their own.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Outcome::Success => StatusCode::OK,
+            Outcome::CompileError => StatusCode::BAD_REQUEST,
+            Outcome::Panic { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Outcome::NonZeroExit { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Outcome::Signaled { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Outcome::TimedOut => StatusCode::UNPROCESSABLE_ENTITY,
+            Outcome::LimitExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    // Outcome of a failed build, if the project's code is to blame
+    pub fn of_build_error(error: &CompilerApiError) -> Option<Self> {
+        match error {
+            CompilerApiError::CompileError { .. } => Some(Outcome::CompileError),
+            CompilerApiError::LimitExceeded { limit, .. } => Some(Self::of_limit(*limit)),
+            _ => None,
+        }
+    }
+
+    // Outcome of a finished run; `limit` is the limit it was stopped for
+    pub fn of_run(exit_code: Option<i32>, signal: Option<i32>, stderr: &str, limit: Option<LimitKind>) -> Self {
+        if let Some(limit) = limit {
+            return Self::of_limit(limit);
+        }
+        // panic=abort profiles end in SIGABRT instead of exit code 101
+        let panicking = exit_code == Some(PANIC_EXIT_CODE) || signal == Some(libc::SIGABRT);
+        if let Some(outcome) = panicking.then(|| last_panic(stderr)).flatten() {
+            return outcome;
+        }
+        match (exit_code, signal) {
+            (_, Some(signal)) => Outcome::Signaled { signal },
+            (Some(0), None) => Outcome::Success,
+            (Some(exit_code), None) => Outcome::NonZeroExit { exit_code },
+            (None, None) => Outcome::NonZeroExit { exit_code: -1 },
+        }
+    }
+
+    fn of_limit(limit: LimitKind) -> Self {
+        match limit {
+            LimitKind::BuildTimeout | LimitKind::RunTimeout => Outcome::TimedOut,
+            limit => Outcome::LimitExceeded { limit },
+        }
+    }
+}
+
+// The last panic the default hook reported on stderr, which is the one that
+// ended the process:
+//
+//     thread 'main' (2) panicked at src/main.rs:4:5:
+//     boom
+//     note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+fn last_panic(stderr: &str) -> Option<Outcome> {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let (index, thread, at) = lines.iter().enumerate().rev().find_map(|(index, line)| {
+        let rest = line.strip_prefix("thread '")?;
+        let (thread, rest) = rest.split_once('\'')?;
+        let (_, at) = rest.split_once(" panicked at ")?;
+        Some((index, thread, at))
+    })?;
+
+    // Before Rust 1.73 the message came first: panicked at 'boom', src/main.rs:4:5
+    // A message spanning several lines ends on a later line with the location.
+    let Some(location) = at.strip_suffix(':') else {
+        let first = at.strip_prefix('\'').unwrap_or(at);
+        let mut message: Vec<&str> = Vec::new();
+        for line in std::iter::once(first).chain(lines[index + 1..].iter().copied()) {
+            let end = line.rsplit_once("', ").and_then(|(last, location)| Some((last, parse_location(location)?)));
+            if let Some((last, location)) = end {
+                message.push(last);
+                return Some(Outcome::Panic { thread: thread.to_string(), message: message.join("\n"), location: Some(location) });
+            }
+            message.push(line);
+        }
+        return Some(Outcome::Panic { thread: thread.to_string(), message: first.to_string(), location: None });
+    };
+    let message: Vec<&str> = lines[index + 1..].iter()
+        .take_while(|line| !line.starts_with("note: ") && !line.starts_with("stack backtrace:"))
+        .copied()
+        .collect();
+    Some(Outcome::Panic {
+        thread: thread.to_string(),
+        message: message.join("\n"),
+        location: parse_location(location),
+    })
+}
+
+// file:line:column; the file name itself may contain colons
+fn parse_location(location: &str) -> Option<PanicLocation> {
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?.to_string();
+    Some(PanicLocation { file, line, column })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKTRACE_NOTE: &str = "note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace";
+
+    fn location(file: &str, line: u32, column: u32) -> Option<PanicLocation> {
+        Some(PanicLocation { file: file.to_string(), line, column })
+    }
+
+    #[test]
+    fn parses_panics_in_the_current_format() {
+        let stderr = format!("thread 'main' (2) panicked at src/main.rs:4:5:\nboom\nacross lines\n{}\n", BACKTRACE_NOTE);
+        assert_eq!(Outcome::of_run(Some(101), None, &stderr, None), Outcome::Panic {
+            thread: "main".to_string(),
+            message: "boom\nacross lines".to_string(),
+            location: location("src/main.rs", 4, 5),
+        });
+    }
+
+    #[test]
+    fn parses_panics_in_the_pre_1_73_format() {
+        let stderr = format!("thread 'main' panicked at 'it's, over', src/ma:in.rs:4:5\n{}\n", BACKTRACE_NOTE);
+        assert_eq!(Outcome::of_run(Some(101), None, &stderr, None), Outcome::Panic {
+            thread: "main".to_string(),
+            message: "it's, over".to_string(),
+            location: location("src/ma:in.rs", 4, 5),
+        });
+        // A multi-line message runs on until the line with the location
+        let stderr = format!("thread 'main' panicked at 'first\nsecond', src/main.rs:4:5\n{}\n", BACKTRACE_NOTE);
+        assert_eq!(Outcome::of_run(Some(101), None, &stderr, None), Outcome::Panic {
+            thread: "main".to_string(),
+            message: "first\nsecond".to_string(),
+            location: location("src/main.rs", 4, 5),
+        });
+        // Cut off before the location
+        let stderr = "thread 'main' panicked at 'first\nsecond";
+        assert_eq!(Outcome::of_run(Some(101), None, stderr, None), Outcome::Panic {
+            thread: "main".to_string(),
+            message: "first".to_string(),
+            location: None,
+        });
+    }
+
+    #[test]
+    fn file_names_may_contain_colons() {
+        assert_eq!(parse_location("C:\\src\\main.rs:12:3"), location("C:\\src\\main.rs", 12, 3));
+        assert_eq!(parse_location("src/a:b.rs:1:2"), location("src/a:b.rs", 1, 2));
+        assert_eq!(parse_location("src/main.rs:4"), None);
+        assert_eq!(parse_location("src/main.rs:x:4"), None);
+    }
+
+    #[test]
+    fn the_last_panic_ended_the_process() {
+        let stderr = "thread 'worker' panicked at src/lib.rs:1:1:\nfirst\nthread 'main' panicked at src/main.rs:9:9:\nsecond\n";
+        let Some(Outcome::Panic { thread, message, .. }) = last_panic(stderr) else { panic!("no panic parsed") };
+        assert_eq!((thread.as_str(), message.as_str()), ("main", "second"));
+    }
+
+    #[test]
+    fn panic_reports_alone_do_not_make_a_panic() {
+        let stderr = "thread 'main' panicked at src/main.rs:4:5:\nboom\n";
+        assert_eq!(Outcome::of_run(Some(0), None, stderr, None), Outcome::Success);
+        assert_eq!(Outcome::of_run(Some(3), None, stderr, None), Outcome::NonZeroExit { exit_code: 3 });
+        assert_eq!(Outcome::of_run(Some(101), None, "", None), Outcome::NonZeroExit { exit_code: 101 });
+        assert!(matches!(Outcome::of_run(None, Some(libc::SIGABRT), stderr, None), Outcome::Panic { .. }));
+        assert_eq!(Outcome::of_run(None, Some(libc::SIGABRT), "", None), Outcome::Signaled { signal: libc::SIGABRT });
+    }
+
+    #[test]
+    fn limits_come_first_and_decide_the_status() {
+        assert_eq!(Outcome::of_run(None, Some(libc::SIGKILL), "", Some(LimitKind::RunTimeout)), Outcome::TimedOut);
+        let outcome = Outcome::of_run(Some(101), None, "", Some(LimitKind::OutputSize));
+        assert_eq!(outcome, Outcome::LimitExceeded { limit: LimitKind::OutputSize });
+        assert_eq!(outcome.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(Outcome::CompileError.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
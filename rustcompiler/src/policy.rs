@@ -0,0 +1,271 @@
+// Build and dependency policies checked before anything from an upload is
+// compiled
+//
+// Build scripts and proc macros run arbitrary code at compile time. Both are
+// sandboxed like the program itself, but a deployment can also refuse them.
+// The dependency policy keeps unwanted crates out of the enclave altogether.
+
+use semver::{Version, VersionReq};
+
+use crate::lockfile::LockedPackage;
+use crate::metadata::Metadata;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BuildPolicy {
+    pub deny_build_scripts: bool,
+    pub deny_proc_macros: bool,
+}
+
+impl BuildPolicy {
+    // DENY_BUILD_SCRIPTS=true rejects crates with a build.rs anywhere in the
+    // build graph; DENY_PROC_MACROS=true rejects proc-macro dependencies
+    pub fn from_env() -> Self {
+        Self {
+            deny_build_scripts: std::env::var("DENY_BUILD_SCRIPTS").map(|v| v == "true").unwrap_or(false),
+            deny_proc_macros: std::env::var("DENY_PROC_MACROS").map(|v| v == "true").unwrap_or(false),
+        }
+    }
+
+    // Returns one message per offending package
+    pub fn violations(&self, metadata: &Metadata) -> Vec<String> {
+        let mut violations = Vec::new();
+        for package in metadata.build_packages() {
+            if self.deny_build_scripts && package.targets.iter().any(|t| t.is_build_script()) {
+                violations.push(format!("{} {} has a build script", package.name, package.version));
+            }
+            if self.deny_proc_macros && package.targets.iter().any(|t| t.is_proc_macro()) {
+                violations.push(format!("{} {} is a proc-macro crate", package.name, package.version));
+            }
+        }
+        violations
+    }
+}
+
+// A crate name with an optional version range, written "name" or
+// "name@range", e.g. "num-traits@>=0.2, <0.3"
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CrateRule {
+    pub name: String,
+    pub version: Option<VersionReq>,
+}
+
+impl TryFrom<String> for CrateRule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.split_once('@') {
+            Some((name, range)) => Ok(Self {
+                name: name.trim().to_string(),
+                version: Some(VersionReq::parse(range).map_err(|e| format!("Invalid version range in '{}': {}", value, e))?),
+            }),
+            None => Ok(Self { name: value.trim().to_string(), version: None }),
+        }
+    }
+}
+
+impl From<CrateRule> for String {
+    fn from(rule: CrateRule) -> Self {
+        match rule.version {
+            Some(version) => format!("{}@{}", rule.name, version),
+            None => rule.name,
+        }
+    }
+}
+
+impl CrateRule {
+    // Unparseable versions only match rules without a range
+    fn matches(&self, name: &str, version: Option<&str>) -> bool {
+        if self.name != name {
+            return false;
+        }
+        match (&self.version, version.map(Version::parse)) {
+            (None, _) => true,
+            (Some(range), Some(Ok(version))) => range.matches(&version),
+            (Some(_), _) => false,
+        }
+    }
+}
+
+// Operator-defined rules for the crates a project may depend on, read from
+// the JSON file named by DEPENDENCY_POLICY:
+//
+//   {"allow": ["num-bigint", "num-traits@^0.2"], "deny": ["tokio", "hyper"],
+//    "allow_git": false, "allow_path": true, "max_dependencies": 50}
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DependencyPolicy {
+    // When non-empty, every external crate must match one of these
+    pub allow: Vec<CrateRule>,
+    pub deny: Vec<CrateRule>,
+    pub allow_git: bool,
+    pub allow_path: bool,
+    // Limit on external crates in Cargo.lock, direct and transitive
+    pub max_dependencies: Option<usize>,
+}
+
+const DEPENDENCY_TABLES: [&str; 2] = ["dependencies", "build-dependencies"];
+
+// (name, declaration) of the normal and build dependencies in a manifest,
+// including target-specific ones and [workspace.dependencies]. Dev-dependencies
+// are not built.
+fn declared_dependencies(manifest: &toml::Value) -> Vec<(&str, &toml::Value)> {
+    let mut tables: Vec<&toml::Value> = DEPENDENCY_TABLES.iter().filter_map(|t| manifest.get(*t)).collect();
+    tables.extend(manifest.get("workspace").and_then(|w| w.get("dependencies")));
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        for target in targets.values() {
+            tables.extend(DEPENDENCY_TABLES.iter().filter_map(|t| target.get(*t)));
+        }
+    }
+    tables
+        .into_iter()
+        .filter_map(|table| table.as_table())
+        .flat_map(|table| table.iter().map(|(name, declaration)| (name.as_str(), declaration)))
+        .collect()
+}
+
+impl DependencyPolicy {
+    // No DEPENDENCY_POLICY means every dependency is accepted
+    pub fn from_env() -> Result<Self, String> {
+        let Ok(path) = std::env::var("DEPENDENCY_POLICY") else {
+            return Ok(Self { allow_git: true, allow_path: true, ..Self::default() });
+        };
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read dependency policy {}: {}", path, e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Invalid dependency policy {}: {}", path, e))
+    }
+
+    fn check_crate(&self, name: &str, version: Option<&str>, violations: &mut Vec<String>) {
+        let label = match version {
+            Some(version) => format!("{} {}", name, version),
+            None => name.to_string(),
+        };
+        if let Some(rule) = self.deny.iter().find(|rule| rule.matches(name, version)) {
+            violations.push(format!("{} is denied by the rule '{}'", label, String::from(rule.clone())));
+        } else if !self.allow.is_empty() && !self.allow.iter().any(|rule| rule.matches(name, version)) {
+            violations.push(format!("{} is not on the allowlist", label));
+        }
+    }
+
+    // Checks the declarations of the project's manifests (the root one plus
+    // any workspace members) and every locked package. Direct dependencies
+    // the Cargo.lock doesn't pin, or all of them without a Cargo.lock, can
+    // only be checked by name; cargo adds them when it resolves the project.
+    pub fn violations(&self, manifests: &[toml::Value], locked: Option<&[LockedPackage]>) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        for (name, declaration) in manifests.iter().flat_map(declared_dependencies) {
+            let name = declaration.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+            if !self.allow_git && declaration.get("git").is_some() {
+                violations.push(format!("{} is a git dependency", name));
+            }
+            if !self.allow_path && declaration.get("path").is_some() {
+                violations.push(format!("{} is a path dependency", name));
+            }
+            let pinned = locked.is_some_and(|locked| locked.iter().any(|package| package.name == name));
+            if !pinned && declaration.get("path").is_none() {
+                self.check_crate(name, None, &mut violations);
+            }
+        }
+
+        if let Some(locked) = locked {
+            let external: Vec<&LockedPackage> = locked.iter().filter(|p| p.source.is_some()).collect();
+            for package in &external {
+                if !self.allow_git && package.source.as_deref().is_some_and(|s| s.starts_with("git+")) {
+                    violations.push(format!("{} {} comes from git", package.name, package.version));
+                }
+                self.check_crate(&package.name, Some(&package.version), &mut violations);
+            }
+            if let Some(max) = self.max_dependencies {
+                if external.len() > max {
+                    violations.push(format!("{} dependencies exceed the maximum of {}", external.len(), max));
+                }
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        violations.retain(|violation| seen.insert(violation.clone()));
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(json: &str) -> DependencyPolicy {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn manifest(text: &str) -> toml::Value {
+        toml::from_str(text).unwrap()
+    }
+
+    fn locked(name: &str, version: &str, source: &str) -> LockedPackage {
+        LockedPackage { name: name.to_string(), version: version.to_string(), source: Some(source.to_string()), checksum: None }
+    }
+
+    const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    #[test]
+    fn crate_rules_parse_and_match_version_ranges() {
+        let rule = CrateRule::try_from("num-traits@>=0.2, <0.3".to_string()).unwrap();
+        assert!(rule.matches("num-traits", Some("0.2.19")));
+        assert!(!rule.matches("num-traits", Some("0.3.0")));
+        assert!(!rule.matches("num-traits", None));
+        assert!(!rule.matches("num-bigint", Some("0.2.0")));
+        assert!(CrateRule::try_from("tokio".to_string()).unwrap().matches("tokio", Some("not semver")));
+        assert!(CrateRule::try_from("tokio@nope".to_string()).is_err());
+        assert!(serde_json::from_str::<DependencyPolicy>(r#"{"allow": [], "unknown": 1}"#).is_err());
+    }
+
+    #[test]
+    fn checks_declared_dependencies_without_a_lockfile() {
+        let policy = policy(r#"{"deny": ["tokio"], "allow_git": false, "allow_path": false}"#);
+        let manifests = [manifest(r#"
+[dependencies]
+runtime = { package = "tokio", version = "1" }
+fork = { git = "https://example.com/fork" }
+local = { path = "../local" }
+[dev-dependencies]
+tokio = "1"
+[target.'cfg(unix)'.build-dependencies]
+tokio = "1"
+"#)];
+        assert_eq!(policy.violations(&manifests, None), [
+            "fork is a git dependency",
+            "local is a path dependency",
+            "tokio is denied by the rule 'tokio'",
+        ]);
+    }
+
+    #[test]
+    fn checks_every_locked_package() {
+        let policy = policy(r#"{"allow": ["itoa@^1", "ryu"], "allow_path": true, "max_dependencies": 2}"#);
+        let locked = [
+            LockedPackage { name: "app".to_string(), version: "0.1.0".to_string(), source: None, checksum: None },
+            locked("itoa", "1.0.11", CRATES_IO),
+            locked("itoa", "0.4.8", CRATES_IO),
+            locked("ryu", "1.0.18", "git+https://example.com/ryu#abc"),
+        ];
+        assert_eq!(policy.violations(&[manifest("[dependencies]\nitoa = \"1\"")], Some(&locked)), [
+            "itoa 0.4.8 is not on the allowlist",
+            "ryu 1.0.18 comes from git",
+            "3 dependencies exceed the maximum of 2",
+        ]);
+    }
+
+    #[test]
+    fn checks_declared_dependencies_missing_from_the_lockfile() {
+        let policy = policy(r#"{"deny": ["tokio"], "allow_path": true}"#);
+        let manifests = [manifest("[dependencies]\nitoa = \"1\"\ntokio = \"1\"")];
+        let locked = [locked("itoa", "1.0.11", CRATES_IO)];
+        assert_eq!(policy.violations(&manifests, Some(&locked)), ["tokio is denied by the rule 'tokio'"]);
+    }
+
+    #[test]
+    fn no_policy_accepts_everything() {
+        let policy = DependencyPolicy { allow_git: true, allow_path: true, ..DependencyPolicy::default() };
+        let manifests = [manifest("[dependencies]\nfork = { git = \"https://example.com/fork\" }\nlocal = { path = \"x\" }")];
+        assert!(policy.violations(&manifests, Some(&[locked("fork", "0.1.0", "git+https://example.com/fork#abc")])).is_empty());
+    }
+}
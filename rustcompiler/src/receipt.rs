@@ -0,0 +1,174 @@
+// Signed execution receipts
+//
+// The service generates an Ed25519 key at startup that never leaves the
+// enclave. The public key is bound to a quote once (see /attestation/pubkey),
+// so verifiers can check any number of receipts against that single key.
+
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub const RECEIPT_VERSION: u32 = 1;
+const RECEIPT_DOMAIN: &[u8] = b"walrustee/receipt/v1\n";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ExecutionReceipt {
+    pub version: u32,
+    // Code that ran: the uploaded tarball, or for /execute the source tree of
+    // the cached build. A receipt records exactly one of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tarball_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_sha256: Option<String>,
+    pub args: Vec<String>,
+    pub stdout: String,
+    pub exit_code: i32,
+    pub started_at: String,
+    pub finished_at: String,
+    pub toolchain: String,
+    // Cargo.lock the binary was built with; omitted by older receipts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile_sha256: Option<String>,
+    // Executable that ran, recorded for reproducible builds only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_sha256: Option<String>,
+    // Program input besides the args, omitted when the run had none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    // Input file name to SHA-256
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub input_files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SignedReceipt {
+    pub receipt: ExecutionReceipt,
+    // Base64 Ed25519 signature over the domain-separated receipt JSON
+    pub signature: String,
+    // Base64 Ed25519 public key, as published by /attestation/pubkey
+    pub public_key: String,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// Bytes covered by the signature. Field order of ExecutionReceipt is fixed,
+// so serde_json output is stable for a given receipt.
+pub fn signing_bytes(receipt: &ExecutionReceipt) -> Result<Vec<u8>, String> {
+    let json = serde_json::to_vec(receipt).map_err(|e| format!("Failed to serialize receipt: {}", e))?;
+    let mut bytes = RECEIPT_DOMAIN.to_vec();
+    bytes.extend_from_slice(&json);
+    Ok(bytes)
+}
+
+pub struct ReceiptSigner {
+    key: SigningKey,
+}
+
+impl ReceiptSigner {
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut rand_core::OsRng),
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    pub fn sign(&self, receipt: ExecutionReceipt) -> Result<SignedReceipt, String> {
+        let signature = self.key.sign(&signing_bytes(&receipt)?);
+        Ok(SignedReceipt {
+            receipt,
+            signature: base64::engine::general_purpose::STANDARD.encode(signature.to_bytes()),
+            public_key: base64::engine::general_purpose::STANDARD.encode(self.public_key()),
+        })
+    }
+}
+
+// Checks the receipt signature against the given base64 public key
+pub fn verify_signature(signed: &SignedReceipt, public_key: &str) -> Result<(), String> {
+    let engine = base64::engine::general_purpose::STANDARD;
+
+    let key_bytes: [u8; 32] = engine
+        .decode(public_key)
+        .map_err(|e| format!("Invalid public key encoding: {}", e))?
+        .try_into()
+        .map_err(|_| "Public key must be 32 bytes".to_string())?;
+    let key = VerifyingKey::from_bytes(&key_bytes).map_err(|e| format!("Invalid public key: {}", e))?;
+
+    let signature_bytes: [u8; 64] = engine
+        .decode(&signed.signature)
+        .map_err(|e| format!("Invalid signature encoding: {}", e))?
+        .try_into()
+        .map_err(|_| "Signature must be 64 bytes".to_string())?;
+    let signature = Signature::from_bytes(&signature_bytes);
+
+    key.verify(&signing_bytes(&signed.receipt)?, &signature)
+        .map_err(|e| format!("Signature verification failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt() -> ExecutionReceipt {
+        ExecutionReceipt {
+            version: RECEIPT_VERSION,
+            tarball_sha256: Some(sha256_hex(b"tarball")),
+            source_sha256: None,
+            args: vec!["10".to_string()],
+            stdout: "55\n".to_string(),
+            exit_code: 0,
+            started_at: "2026-01-01T00:00:00Z".to_string(),
+            finished_at: "2026-01-01T00:00:01Z".to_string(),
+            toolchain: "1.95.0".to_string(),
+            lockfile_sha256: None,
+            binary_sha256: None,
+            stdin_sha256: None,
+            env: BTreeMap::new(),
+            input_files: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let signer = ReceiptSigner::generate();
+        let signed = signer.sign(receipt()).unwrap();
+        assert_eq!(verify_signature(&signed, &signed.public_key), Ok(()));
+
+        // Through JSON, the way clients get it
+        let json = serde_json::to_string(&signed).unwrap();
+        let parsed: SignedReceipt = serde_json::from_str(&json).unwrap();
+        assert_eq!(verify_signature(&parsed, &signed.public_key), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tampered_receipts_and_other_keys() {
+        let signed = ReceiptSigner::generate().sign(receipt()).unwrap();
+
+        let mut tampered = signed.clone();
+        tampered.receipt.stdout = "56\n".to_string();
+        assert!(verify_signature(&tampered, &signed.public_key).is_err());
+        let mut tampered = signed.clone();
+        tampered.receipt.env.insert("RUST_LOG".to_string(), "debug".to_string());
+        assert!(verify_signature(&tampered, &signed.public_key).is_err());
+
+        let other = ReceiptSigner::generate().sign(receipt()).unwrap();
+        assert!(verify_signature(&signed, &other.public_key).is_err());
+        assert!(verify_signature(&signed, "not base64").is_err());
+    }
+
+    #[test]
+    fn optional_fields_stay_out_of_the_signed_bytes() {
+        let bytes = signing_bytes(&receipt()).unwrap();
+        let json = std::str::from_utf8(&bytes[RECEIPT_DOMAIN.len()..]).unwrap();
+        for field in ["lockfile_sha256", "binary_sha256", "stdin_sha256", "env", "input_files"] {
+            assert!(!json.contains(field), "{}", field);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Offline crate registry the service resolves dependencies from
+//
+// When REGISTRY_MIRROR is set, crates.io is replaced by a preloaded local
+// directory and cargo never touches the network. The mirror is either the
+// output of `cargo vendor` (one directory per crate) or a cargo local
+// registry (`index/` plus `.crate` files, e.g. from cargo-local-registry).
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::lockfile::LockedPackage;
+
+const SOURCE_NAME: &str = "walrustee-mirror";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MirrorKind {
+    Directory,
+    LocalRegistry,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MissingDependency {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryMirror {
+    pub path: PathBuf,
+    pub kind: MirrorKind,
+}
+
+// Name and version from a vendored crate's Cargo.toml
+fn vendored_package(manifest: &Path) -> Option<(String, String)> {
+    let manifest: toml::Value = fs::read_to_string(manifest).ok()?.parse().ok()?;
+    let package = manifest.get("package")?;
+    Some((
+        package.get("name")?.as_str()?.to_string(),
+        package.get("version")?.as_str()?.to_string(),
+    ))
+}
+
+impl RegistryMirror {
+    // REGISTRY_MIRROR=/path/to/mirror; unset means dependencies are fetched
+    // from crates.io before the offline build
+    pub fn from_env() -> Option<Self> {
+        let path = PathBuf::from(std::env::var("REGISTRY_MIRROR").ok().filter(|v| !v.is_empty())?);
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        let kind = if path.join("index").is_dir() {
+            MirrorKind::LocalRegistry
+        } else {
+            MirrorKind::Directory
+        };
+        Some(Self { path, kind })
+    }
+
+    // `--config` flags for cargo that replace crates.io with the mirror
+    pub fn cargo_args(&self) -> Vec<String> {
+        let key = match self.kind {
+            MirrorKind::Directory => "directory",
+            MirrorKind::LocalRegistry => "local-registry",
+        };
+        vec![
+            "--config".to_string(),
+            format!("source.crates-io.replace-with=\"{}\"", SOURCE_NAME),
+            "--config".to_string(),
+            format!("source.{}.{}={:?}", SOURCE_NAME, key, self.path.display().to_string()),
+            "--config".to_string(),
+            "net.offline=true".to_string(),
+        ]
+    }
+
+    // Name and version of every crate available in the mirror
+    fn available(&self) -> Result<HashSet<(String, String)>, String> {
+        let entries = fs::read_dir(&self.path)
+            .map_err(|e| format!("Failed to read registry mirror {}: {}", self.path.display(), e))?;
+        let mut available = HashSet::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match self.kind {
+                MirrorKind::Directory => {
+                    if let Some(package) = vendored_package(&path.join("Cargo.toml")) {
+                        available.insert(package);
+                    }
+                }
+                MirrorKind::LocalRegistry => {
+                    // name-version.crate; the version is everything after the
+                    // last '-' that is followed by a digit
+                    let Some(stem) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".crate")) else {
+                        continue;
+                    };
+                    let split = stem.char_indices().rev().find(|&(i, c)| c == '-' && stem[i + 1..].starts_with(|c: char| c.is_ascii_digit()));
+                    if let Some((i, _)) = split {
+                        available.insert((stem[..i].to_string(), stem[i + 1..].to_string()));
+                    }
+                }
+            }
+        }
+        Ok(available)
+    }
+
+    // Locked crates the mirror cannot provide. Git dependencies and other
+    // registries are always missing since only crates.io is mirrored.
+    pub fn missing(&self, packages: &[LockedPackage]) -> Result<Vec<MissingDependency>, String> {
+        let available = self.available()?;
+        Ok(packages
+            .iter()
+            .filter(|package| package.source.is_some())
+            .filter(|package| !package.is_crates_io() || !available.contains(&(package.name.clone(), package.version.clone())))
+            .map(|package| MissingDependency {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                source: package.source.clone(),
+            })
+            .collect())
+    }
+}
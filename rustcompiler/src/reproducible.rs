@@ -0,0 +1,67 @@
+// Settings for reproducible builds
+//
+// A release build normally embeds the absolute paths it was built from
+// (./projects/{user_id}/..., the cargo registry, the target directory) and
+// its layout depends on how rustc split the crate into codegen units. In
+// reproducible mode every such path is remapped to a fixed prefix, the build
+// time is pinned through SOURCE_DATE_EPOCH and each crate is compiled as a
+// single codegen unit, so anyone with the same toolchain can rebuild the
+// tarball and get a byte-identical executable.
+
+use std::path::{Path, PathBuf};
+
+// Thu Jan  1 00:00:01 UTC 1970; some tools treat 0 as unset
+pub const SOURCE_DATE_EPOCH: &str = "1";
+
+// Fixed prefixes the build paths are remapped to
+pub const SOURCE_PREFIX: &str = "/build/src";
+pub const TARGET_PREFIX: &str = "/build/target";
+pub const CARGO_HOME_PREFIX: &str = "/cargo";
+pub const MIRROR_PREFIX: &str = "/cargo/mirror";
+
+// Paths that end up in the binary and where they are remapped to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildPaths {
+    pub project_dir: PathBuf,
+    pub target_dir: PathBuf,
+    pub cargo_home: PathBuf,
+    pub mirror: Option<PathBuf>,
+}
+
+impl BuildPaths {
+    // rustc applies the last matching remap, so nested paths come last
+    fn remaps(&self) -> Vec<(&Path, &str)> {
+        let mut remaps = vec![(self.cargo_home.as_path(), CARGO_HOME_PREFIX)];
+        if let Some(mirror) = &self.mirror {
+            remaps.push((mirror.as_path(), MIRROR_PREFIX));
+        }
+        remaps.push((self.project_dir.as_path(), SOURCE_PREFIX));
+        remaps.push((self.target_dir.as_path(), TARGET_PREFIX));
+        remaps
+    }
+
+    // Environment for a cargo build with `profile`. CARGO_ENCODED_RUSTFLAGS
+    // also replaces any rustflags the project sets in .cargo/config.toml, and
+    // the profile variables take precedence over [profile.*] in Cargo.toml.
+    pub fn cargo_env(&self, profile: &str) -> Vec<(String, String)> {
+        let profile_var = profile.to_ascii_uppercase().replace('-', "_");
+        let rustflags: Vec<String> = self.remaps()
+            .into_iter()
+            .map(|(from, to)| format!("--remap-path-prefix={}={}", from.display(), to))
+            .collect();
+        vec![
+            ("CARGO_ENCODED_RUSTFLAGS".to_string(), rustflags.join("\x1f")),
+            ("SOURCE_DATE_EPOCH".to_string(), SOURCE_DATE_EPOCH.to_string()),
+            (format!("CARGO_PROFILE_{}_CODEGEN_UNITS", profile_var), "1".to_string()),
+            (format!("CARGO_PROFILE_{}_INCREMENTAL", profile_var), "false".to_string()),
+            ("CARGO_INCREMENTAL".to_string(), "0".to_string()),
+        ]
+    }
+}
+
+// Where cargo keeps the registry sources that dependencies are built from
+pub fn cargo_home() -> PathBuf {
+    std::env::var("CARGO_HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".cargo")
+    })
+}
@@ -0,0 +1,558 @@
+// Linux namespace sandbox for untrusted binaries
+//
+// tee-exec uses this to start a program in fresh user, mount, PID, IPC, UTS
+// and (by default) network namespaces. The program sees a read-only root made
+// of the system library directories, the paths explicitly bound in, a private
+// /proc, a few device nodes and size-capped scratch tmpfs mounts at /tmp and
+// /work. A seccomp filter blocks the syscalls that would let it leave or
+// reconfigure the sandbox.
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+// Read-only system directories needed to run dynamically linked binaries
+const SYSTEM_PATHS: &[&str] = &["/usr", "/lib", "/lib32", "/lib64", "/bin", "/etc/ld.so.cache", "/etc/alternatives"];
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+pub const WORKDIR: &str = "/work";
+// uid/gid the program runs as inside the sandbox; execve drops all
+// capabilities because it is not 0
+const SANDBOX_ID: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BindMount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub writable: bool,
+}
+
+impl BindMount {
+    // Parses SRC or SRC:DST
+    pub fn parse(spec: &str, writable: bool) -> Self {
+        let (source, target) = match spec.split_once(':') {
+            Some((source, target)) => (source, target),
+            None => (spec, spec),
+        };
+        Self {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            writable,
+        }
+    }
+
+    pub fn to_arg(&self) -> String {
+        format!("{}:{}", self.source.display(), self.target.display())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SandboxSpec {
+    pub mounts: Vec<BindMount>,
+    pub allow_network: bool,
+    pub workdir: PathBuf,
+    pub scratch_bytes: u64,
+}
+
+// Service-side sandbox configuration
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SandboxPolicy {
+    pub enabled: bool,
+    pub allow_network: bool,
+    pub scratch_bytes: u64,
+    // Environment variables a request may set for the program; an entry
+    // ending in `*` allows every name with that prefix
+    pub env_allowlist: Vec<String>,
+}
+
+impl SandboxPolicy {
+    // SANDBOX=off disables the namespaces; SANDBOX_ALLOW_NETWORK=true keeps the
+    // host network; SANDBOX_SCRATCH_BYTES sizes /tmp and /work;
+    // SANDBOX_ENV_ALLOWLIST lists the variables requests may set
+    pub fn from_env() -> Self {
+        Self {
+            enabled: std::env::var("SANDBOX").map(|v| v != "off").unwrap_or(true),
+            allow_network: std::env::var("SANDBOX_ALLOW_NETWORK").map(|v| v == "true").unwrap_or(false),
+            scratch_bytes: std::env::var("SANDBOX_SCRATCH_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(64 * 1024 * 1024),
+            env_allowlist: std::env::var("SANDBOX_ENV_ALLOWLIST")
+                .unwrap_or_else(|_| "RUST_BACKTRACE,RUST_LOG".to_string())
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn allows_env(&self, name: &str) -> bool {
+        self.env_allowlist.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == allowed,
+        })
+    }
+
+    // Arguments for tee-exec. Builds pass `network: false` whatever the
+    // policy says, since dependencies are fetched beforehand.
+    pub fn exec_args(&self, mounts: &[BindMount], network: bool) -> Vec<String> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut args = vec!["--sandbox".to_string(), "--scratch".to_string(), self.scratch_bytes.to_string()];
+        if network {
+            args.push("--share-net".to_string());
+        }
+        for mount in mounts {
+            args.push(if mount.writable { "--bind" } else { "--ro-bind" }.to_string());
+            args.push(mount.to_arg());
+        }
+        args
+    }
+}
+
+// PATH of user programs and builds, with only the system directories
+const SYSTEM_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+// CARGO_HOME and RUSTUP_HOME of the service
+fn tool_homes() -> (String, String) {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let cargo_home = std::env::var("CARGO_HOME").unwrap_or_else(|_| format!("{}/.cargo", home));
+    let rustup_home = std::env::var("RUSTUP_HOME").unwrap_or_else(|_| format!("{}/.rustup", home));
+    (cargo_home, rustup_home)
+}
+
+// The whole environment a user program starts with, before the variables the
+// request sets. Nothing of the service's own environment is passed on.
+pub fn program_env() -> Vec<(String, String)> {
+    vec![
+        ("PATH".to_string(), SYSTEM_PATH.to_string()),
+        ("HOME".to_string(), "/tmp".to_string()),
+        ("LANG".to_string(), "C.UTF-8".to_string()),
+    ]
+}
+
+// The environment the sandboxed build starts with: just enough to find cargo
+// and the toolchains. The toolchain and build settings are added to it.
+pub fn build_env() -> Vec<(String, String)> {
+    let (cargo_home, rustup_home) = tool_homes();
+    vec![
+        ("PATH".to_string(), format!("{}/bin:{}", cargo_home, SYSTEM_PATH)),
+        ("HOME".to_string(), "/tmp".to_string()),
+        ("LANG".to_string(), "C.UTF-8".to_string()),
+        ("CARGO_HOME".to_string(), cargo_home),
+        ("RUSTUP_HOME".to_string(), rustup_home),
+    ]
+}
+
+// Read-only mounts for the cargo and rustup installations, so cargo can build
+// inside the sandbox from the already fetched registry
+pub fn toolchain_mounts() -> Vec<BindMount> {
+    let (cargo_home, rustup_home) = tool_homes();
+
+    [cargo_home, rustup_home]
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+        .map(|path| BindMount {
+            source: path.clone(),
+            target: path,
+            writable: false,
+        })
+        .collect()
+}
+
+fn os_error(context: &str) -> String {
+    format!("{}: {}", context, std::io::Error::last_os_error())
+}
+
+fn cstring(path: &Path) -> Result<CString, String> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| format!("Path contains a NUL byte: {}", path.display()))
+}
+
+fn mount(source: Option<&Path>, target: &Path, fstype: Option<&str>, flags: libc::c_ulong, data: Option<&str>) -> Result<(), String> {
+    let source = source.map(cstring).transpose()?;
+    let target_c = cstring(target)?;
+    let fstype = fstype.map(|t| CString::new(t).unwrap());
+    let data = data.map(|d| CString::new(d).unwrap());
+
+    let result = unsafe {
+        libc::mount(
+            source.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            target_c.as_ptr(),
+            fstype.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            flags,
+            data.as_ref().map_or(std::ptr::null(), |s| s.as_ptr() as *const libc::c_void),
+        )
+    };
+    if result != 0 {
+        return Err(os_error(&format!("Failed to mount {}", target.display())));
+    }
+    Ok(())
+}
+
+// Bind mounts `source` at `root/target`, creating the mount point to match
+fn bind(root: &Path, source: &Path, target: &Path, writable: bool) -> Result<(), String> {
+    let inside = root.join(target.strip_prefix("/").unwrap_or(target));
+
+    // Recreate symlinks such as /lib -> usr/lib instead of following them
+    if let Ok(link) = std::fs::read_link(source) {
+        if let Some(parent) = inside.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        return std::os::unix::fs::symlink(&link, &inside)
+            .map_err(|e| format!("Failed to link {}: {}", inside.display(), e));
+    }
+
+    if source.is_dir() {
+        std::fs::create_dir_all(&inside).map_err(|e| format!("Failed to create {}: {}", inside.display(), e))?;
+    } else {
+        if let Some(parent) = inside.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&inside, b"").map_err(|e| format!("Failed to create {}: {}", inside.display(), e))?;
+    }
+
+    mount(Some(source), &inside, None, libc::MS_BIND | libc::MS_REC, None)?;
+    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_NOSUID;
+    if !DEVICES.iter().any(|device| source == Path::new(device)) {
+        flags |= libc::MS_NODEV;
+    }
+    if !writable {
+        flags |= libc::MS_RDONLY;
+    }
+    mount(None, &inside, None, flags, None)
+}
+
+fn write_file(path: &str, contents: &str) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// Filters installed on the program; when several match, the kernel applies
+// the most restrictive action
+fn seccomp_filters() -> Result<Vec<seccompiler::BpfProgram>, String> {
+    use seccompiler::{SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter, SeccompRule};
+
+    let mut denied: Vec<i64> = vec![
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_syslog,
+        libc::SYS_userfaultfd,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_clock_adjtime,
+        libc::SYS_adjtimex,
+        libc::SYS_fsopen,
+        libc::SYS_fsconfig,
+        libc::SYS_fsmount,
+        libc::SYS_fspick,
+        libc::SYS_move_mount,
+        libc::SYS_open_tree,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    denied.extend([libc::SYS_iopl, libc::SYS_ioperm, libc::SYS_kexec_file_load]);
+
+    let mut rules: BTreeMap<i64, Vec<SeccompRule>> = denied.into_iter().map(|syscall| (syscall, Vec::new())).collect();
+
+    // clone may not create namespaces either
+    let namespace_flags = [
+        libc::CLONE_NEWUSER,
+        libc::CLONE_NEWNS,
+        libc::CLONE_NEWPID,
+        libc::CLONE_NEWNET,
+        libc::CLONE_NEWIPC,
+        libc::CLONE_NEWUTS,
+        libc::CLONE_NEWCGROUP,
+    ];
+    let clone_rules = namespace_flags
+        .iter()
+        .map(|&flag| {
+            let flag = flag as u64;
+            SeccompCondition::new(0, SeccompCmpArgLen::Qword, SeccompCmpOp::MaskedEq(flag), flag)
+                .and_then(|condition| SeccompRule::new(vec![condition]))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to build seccomp rule: {}", e))?;
+    rules.insert(libc::SYS_clone, clone_rules);
+
+    // clone3 passes its flags in memory where the filter cannot see them;
+    // ENOSYS makes libc fall back to clone, which is checked above
+    let clone3 = [(libc::SYS_clone3, Vec::new())].into_iter().collect();
+
+    [(rules, libc::EPERM), (clone3, libc::ENOSYS)]
+        .into_iter()
+        .map(|(rules, errno)| {
+            let arch = std::env::consts::ARCH.try_into().map_err(|e| format!("Unsupported architecture: {}", e))?;
+            let filter = SeccompFilter::new(rules, SeccompAction::Allow, SeccompAction::Errno(errno as u32), arch)
+                .map_err(|e| format!("Failed to build seccomp filter: {}", e))?;
+            filter.try_into().map_err(|e| format!("Failed to compile seccomp filter: {}", e))
+        })
+        .collect()
+}
+
+// Builds the new root under `root` and switches into it. Runs as PID 1 of
+// the new PID namespace.
+fn enter_root(spec: &SandboxSpec, root: &Path, program: &Path) -> Result<(), String> {
+    // A mount namespace of its own, so pivot_root leaves tee-exec's root alone
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(os_error("Failed to create mount namespace"));
+    }
+    mount(None, Path::new("/"), None, libc::MS_REC | libc::MS_PRIVATE, None)?;
+    mount(Some(Path::new("tmpfs")), root, Some("tmpfs"), libc::MS_NOSUID | libc::MS_NODEV, Some("mode=0755"))?;
+
+    // Scratch space first, so bind mounts can land inside it
+    let scratch = format!("size={},mode=1777", spec.scratch_bytes);
+    for dir in ["tmp", WORKDIR.trim_start_matches('/')] {
+        let target = root.join(dir);
+        std::fs::create_dir_all(&target).map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+        mount(Some(Path::new("tmpfs")), &target, Some("tmpfs"), libc::MS_NOSUID | libc::MS_NODEV, Some(&scratch))?;
+    }
+
+    for path in SYSTEM_PATHS {
+        let path = Path::new(path);
+        if path.exists() {
+            bind(root, path, path, false)?;
+        }
+    }
+    for device in DEVICES {
+        let device = Path::new(device);
+        if device.exists() {
+            bind(root, device, device, true)?;
+        }
+    }
+    for extra in &spec.mounts {
+        bind(root, &extra.source, &extra.target, extra.writable)?;
+    }
+    // The program itself is always visible, read-only, at its host path
+    let visible = SYSTEM_PATHS.iter().any(|path| program.starts_with(path))
+        || spec.mounts.iter().any(|m| m.source == m.target && program.starts_with(&m.source));
+    if !visible {
+        bind(root, program, program, false)?;
+    }
+
+    let proc_dir = root.join("proc");
+    std::fs::create_dir_all(&proc_dir).map_err(|e| format!("Failed to create {}: {}", proc_dir.display(), e))?;
+    mount(Some(Path::new("proc")), &proc_dir, Some("proc"), libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC, None)?;
+
+    let old_root = root.join(".old_root");
+    std::fs::create_dir_all(&old_root).map_err(|e| format!("Failed to create {}: {}", old_root.display(), e))?;
+    let root_c = cstring(root)?;
+    let old_root_c = cstring(&old_root)?;
+    if unsafe { libc::syscall(libc::SYS_pivot_root, root_c.as_ptr(), old_root_c.as_ptr()) } != 0 {
+        return Err(os_error("Failed to pivot root"));
+    }
+    std::env::set_current_dir("/").map_err(|e| format!("Failed to enter new root: {}", e))?;
+    let old_root_c = CString::new("/.old_root").unwrap();
+    if unsafe { libc::umount2(old_root_c.as_ptr(), libc::MNT_DETACH) } != 0 {
+        return Err(os_error("Failed to detach old root"));
+    }
+    let _ = std::fs::remove_dir("/.old_root");
+
+    // Nothing outside the scratch dirs and writable binds stays writable
+    mount(None, Path::new("/"), None, libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV, None)?;
+
+    let hostname = b"sandbox";
+    unsafe {
+        libc::sethostname(hostname.as_ptr() as *const libc::c_char, hostname.len());
+    }
+
+    std::env::set_current_dir(&spec.workdir).map_err(|e| format!("Failed to enter {}: {}", spec.workdir.display(), e))
+}
+
+// How the program ended, as tee-exec reports it with --stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub struct ExitStats {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    // Largest resident set of the program or any process it waited for
+    pub peak_rss_bytes: u64,
+    // User plus system CPU time of the same processes
+    #[serde(default)]
+    pub cpu_time_ms: u64,
+    // Limit events of the run's cgroup: OOM kills and forks rejected by
+    // pids.max. Always zero without cgroup limits.
+    #[serde(default)]
+    pub oom_kills: u64,
+    #[serde(default)]
+    pub rejected_forks: u64,
+}
+
+impl ExitStats {
+    fn new(status: libc::c_int, usage: &libc::rusage) -> Self {
+        let signaled = libc::WIFSIGNALED(status);
+        Self {
+            exit_code: (!signaled).then(|| libc::WEXITSTATUS(status)),
+            signal: signaled.then(|| libc::WTERMSIG(status)),
+            // ru_maxrss is in KiB on Linux
+            peak_rss_bytes: (usage.ru_maxrss.max(0) as u64).saturating_mul(1024),
+            cpu_time_ms: [usage.ru_utime, usage.ru_stime].iter()
+                .map(|time| time.tv_sec.max(0) as u64 * 1000 + time.tv_usec.max(0) as u64 / 1000)
+                .sum(),
+            oom_kills: 0,
+            rejected_forks: 0,
+        }
+    }
+
+    pub fn read_from(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("Failed to parse exit stats: {}", e))
+    }
+
+    pub fn write_to(&self, mut file: &std::fs::File) -> Result<(), String> {
+        use std::io::Write;
+        let json = serde_json::to_vec(self).map_err(|e| format!("Failed to serialize exit stats: {}", e))?;
+        file.write_all(&json).map_err(|e| format!("Failed to write exit stats: {}", e))
+    }
+}
+
+// Waits for `pid` and returns its raw wait status
+pub fn wait_for(pid: libc::pid_t) -> Result<libc::c_int, String> {
+    wait_with_usage(pid).map(|(status, _)| status)
+}
+
+fn wait_with_usage(pid: libc::pid_t) -> Result<(libc::c_int, libc::rusage), String> {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } == pid {
+            return Ok((status, usage));
+        }
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINTR) {
+            return Err(format!("Failed to wait for {}: {}", pid, error));
+        }
+    }
+}
+
+// Waits for `pid` and returns its exit code along with its stats
+pub fn wait_with_stats(pid: libc::pid_t) -> Result<(i32, ExitStats), String> {
+    let (status, usage) = wait_with_usage(pid)?;
+    Ok((exit_code(status), ExitStats::new(status, &usage)))
+}
+
+// Exit code for a wait status, reporting signals on stderr the way a shell would
+fn exit_code(status: libc::c_int) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let description = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(signal)) };
+        eprintln!("tee-exec: program killed by signal {} ({})", signal, description.to_string_lossy());
+        128 + signal
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+// Final step in the program's process; only returns on failure. argv[0] keeps
+// the name the program was invoked by, which multi-call binaries such as the
+// rustup proxies rely on.
+fn exec_program(program: &Path, arg0: &Path, args: &[String], before_exec: impl FnOnce() -> Result<(), String>) -> String {
+    if let Err(e) = before_exec() {
+        return e;
+    }
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return os_error("Failed to set no_new_privs");
+    }
+    let filters = match seccomp_filters() {
+        Ok(filters) => filters,
+        Err(e) => return e,
+    };
+    for filter in filters {
+        if let Err(e) = seccompiler::apply_filter(&filter) {
+            return format!("Failed to apply seccomp filter: {}", e);
+        }
+    }
+    format!("Failed to exec {}: {}", program.display(), Command::new(program).arg0(arg0).args(args).exec())
+}
+
+// Runs `program` inside the sandbox and returns how the helper that
+// supervised it ended: the program's exit code, or 128 plus the signal that
+// killed it, unless the helper was killed by a signal itself. `before_exec`
+// runs in the final child right before the seccomp filter is installed. The
+// exit stats are written to `stats`, which stays open across the pivot.
+pub fn run_sandboxed(
+    spec: &SandboxSpec,
+    program: &Path,
+    args: &[String],
+    stats: Option<&std::fs::File>,
+    before_exec: impl FnOnce() -> Result<(), String>,
+) -> Result<ExitStatus, String> {
+    let resolved = program
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", program.display(), e))?;
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+
+    let mut flags = libc::CLONE_NEWUSER | libc::CLONE_NEWPID | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+    if !spec.allow_network {
+        flags |= libc::CLONE_NEWNET;
+    }
+    if unsafe { libc::unshare(flags) } != 0 {
+        return Err(os_error("Failed to create namespaces"));
+    }
+    write_file("/proc/self/setgroups", "deny")?;
+    write_file("/proc/self/uid_map", &format!("{} {} 1", SANDBOX_ID, uid))?;
+    write_file("/proc/self/gid_map", &format!("{} {} 1", SANDBOX_ID, gid))?;
+
+    let root = std::env::temp_dir().join(format!("tee-exec-{}", std::process::id()));
+    std::fs::create_dir_all(&root).map_err(|e| format!("Failed to create {}: {}", root.display(), e))?;
+
+    // The first child is PID 1 of the new namespace. It builds the root and
+    // then supervises the program, so the program never runs as init.
+    let init = unsafe { libc::fork() };
+    if init < 0 {
+        return Err(os_error("Failed to fork"));
+    }
+    if init == 0 {
+        let code = (|| {
+            enter_root(spec, &root, &resolved)?;
+
+            let child = unsafe { libc::fork() };
+            if child < 0 {
+                return Err(os_error("Failed to fork"));
+            }
+            if child == 0 {
+                eprintln!("tee-exec: {}", exec_program(&resolved, program, args, before_exec));
+                unsafe { libc::_exit(126) };
+            }
+            let (code, exit_stats) = wait_with_stats(child)?;
+            if let Some(stats) = stats {
+                exit_stats.write_to(stats)?;
+            }
+            Ok(code)
+        })()
+        .unwrap_or_else(|e| {
+            eprintln!("tee-exec: {}", e);
+            126
+        });
+        unsafe { libc::_exit(code) };
+    }
+
+    let status = wait_for(init);
+    let _ = std::fs::remove_dir(&root);
+    Ok(ExitStatus::from_raw(status?))
+}
@@ -0,0 +1,215 @@
+// Single-file projects in the style of cargo-script
+//
+// A main.rs can carry its own manifest, either as frontmatter
+//
+//     ---
+//     [dependencies]
+//     itoa = "1"
+//     ---
+//     fn main() { ... }
+//
+// or, as with rust-script, in a ```cargo block of its inner doc comment:
+//
+//     //! ```cargo
+//     //! [dependencies]
+//     //! itoa = "1"
+//     //! ```
+//
+// The manifest is turned into the Cargo.toml of a package named `app`. Only
+// tables that make sense for a single file are accepted.
+
+pub const PACKAGE_NAME: &str = "app";
+const DEFAULT_EDITION: &str = "2021";
+
+// Top-level manifest tables a script may set
+const ALLOWED_TABLES: [&str; 5] = ["package", "dependencies", "features", "profile", "target"];
+// Keys of [package] a script may set; name, version and the targets are fixed
+const ALLOWED_PACKAGE_KEYS: [&str; 2] = ["edition", "rust-version"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    // The embedded manifest, if there is one
+    pub manifest: Option<toml::Table>,
+    // Source to compile. Frontmatter is blanked out line by line, so rustc
+    // reports the same line numbers as in the submitted file.
+    pub body: String,
+}
+
+// Splits off a `---` frontmatter block, which may follow a #! line
+fn frontmatter(source: &str) -> Result<Option<(String, String)>, String> {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let start = match lines.first() {
+        Some(line) if line.starts_with("#!") && !line.starts_with("#![") => 1,
+        _ => 0,
+    };
+    let Some(opening) = lines.get(start).map(|line| line.trim_end()) else {
+        return Ok(None);
+    };
+    let dashes = opening.chars().take_while(|&c| c == '-').count();
+    if dashes < 3 {
+        return Ok(None);
+    }
+    // An optional info string names the tool, e.g. `---cargo`
+    let info = opening[dashes..].trim();
+    if !info.is_empty() && info != "cargo" {
+        return Err(format!("Unsupported frontmatter info string '{}'", info));
+    }
+
+    let fence = &opening[..dashes];
+    let end = lines[start + 1..].iter()
+        .position(|line| line.trim_end() == fence)
+        .map(|offset| start + 1 + offset)
+        .ok_or_else(|| "Unterminated frontmatter".to_string())?;
+
+    let manifest: String = lines[start + 1..end].concat();
+    let mut body = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if (start..=end).contains(&index) {
+            body.push('\n');
+        } else {
+            body.push_str(line);
+        }
+    }
+    Ok(Some((manifest, body)))
+}
+
+// The contents of a ```cargo block in the leading //! comments
+fn doc_comment_manifest(source: &str) -> Option<String> {
+    let mut manifest = None::<String>;
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("#!") && !trimmed.starts_with("#![") {
+            continue;
+        }
+        let Some(doc) = trimmed.strip_prefix("//!") else {
+            break;
+        };
+        let doc = doc.strip_prefix(' ').unwrap_or(doc);
+        match &mut manifest {
+            None if doc.trim_end() == "```cargo" => manifest = Some(String::new()),
+            None => {}
+            Some(_) if doc.trim_end() == "```" => return manifest,
+            Some(text) => {
+                text.push_str(doc);
+                text.push('\n');
+            }
+        }
+    }
+    None
+}
+
+pub fn parse(source: &str) -> Result<Script, String> {
+    let (manifest, body) = match frontmatter(source)? {
+        Some((manifest, body)) => (Some(manifest), body),
+        None => (doc_comment_manifest(source), source.to_string()),
+    };
+    let manifest = manifest
+        .map(|text| text.parse::<toml::Table>().map_err(|e| format!("Invalid embedded manifest: {}", e)))
+        .transpose()?;
+    Ok(Script { manifest, body })
+}
+
+// Cargo.toml for a script with the given embedded manifest
+pub fn manifest(embedded: Option<&toml::Table>) -> Result<String, String> {
+    let mut manifest = embedded.cloned().unwrap_or_default();
+    if let Some(key) = manifest.keys().find(|key| !ALLOWED_TABLES.contains(&key.as_str())) {
+        return Err(format!("Embedded manifests cannot set `{}`", key));
+    }
+
+    let mut package = match manifest.remove("package") {
+        Some(toml::Value::Table(package)) => package,
+        Some(_) => return Err("`package` must be a table".to_string()),
+        None => toml::Table::new(),
+    };
+    if let Some(key) = package.keys().find(|key| !ALLOWED_PACKAGE_KEYS.contains(&key.as_str())) {
+        return Err(format!("Embedded manifests cannot set `package.{}`", key));
+    }
+    package.insert("name".to_string(), PACKAGE_NAME.into());
+    package.insert("version".to_string(), "0.1.0".into());
+    package.entry("edition").or_insert_with(|| DEFAULT_EDITION.into());
+
+    manifest.entry("dependencies").or_insert_with(|| toml::Value::Table(toml::Table::new()));
+
+    // Tables are written in key order; [package] goes first regardless
+    let package = toml::to_string(&package).map_err(|e| format!("Failed to write manifest: {}", e))?;
+    let rest = toml::to_string(&manifest).map_err(|e| format!("Failed to write manifest: {}", e))?;
+    Ok(format!("[package]\n{}\n{}", package, rest))
+}
+
+// Prepends a frontmatter declaring `dependencies` to a source without an
+// embedded manifest
+pub fn with_dependencies(source: &str, dependencies: &toml::Table) -> Result<String, String> {
+    if parse(source)?.manifest.is_some() {
+        return Err("Declare dependencies either in the source or in `dependencies`, not both".to_string());
+    }
+    let mut manifest = toml::Table::new();
+    manifest.insert("dependencies".to_string(), toml::Value::Table(dependencies.clone()));
+    let manifest = toml::to_string(&manifest).map_err(|e| format!("Invalid dependencies: {}", e))?;
+    Ok(format!("---\n{}---\n{}", manifest, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frontmatter_is_blanked_keeping_line_numbers() {
+        let source = "#!/usr/bin/env cargo\n---cargo\n[dependencies]\nitoa = \"1\"\n---\nfn main() {}\n";
+        let script = parse(source).unwrap();
+        assert_eq!(script.body, "#!/usr/bin/env cargo\n\n\n\n\nfn main() {}\n");
+        assert_eq!(script.body.lines().count(), source.lines().count());
+        assert_eq!(script.manifest.unwrap()["dependencies"]["itoa"].as_str(), Some("1"));
+    }
+
+    #[test]
+    fn longer_fences_close_only_on_the_same_length() {
+        let source = "----\n[dependencies]\nx = \"1\"\n---\n----\nfn main() {}";
+        assert!(parse(source).is_err());
+        let source = "----\n[dependencies]\nx = \"1\"\n----\nfn main() {}";
+        assert!(parse(source).unwrap().manifest.is_some());
+    }
+
+    #[test]
+    fn rejects_broken_frontmatter() {
+        assert_eq!(parse("---\n[dependencies]\nfn main() {}").unwrap_err(), "Unterminated frontmatter");
+        assert!(parse("---python\n---\nfn main() {}").unwrap_err().contains("info string"));
+        assert!(parse("---\n[dependencies\n---\nfn main() {}").unwrap_err().starts_with("Invalid embedded manifest"));
+    }
+
+    #[test]
+    fn reads_cargo_blocks_from_inner_doc_comments() {
+        let source = "#!/usr/bin/env rust-script\n//! A script\n//! ```cargo\n//! [dependencies]\n//! itoa = \"1\"\n//! ```\nfn main() {}\n";
+        let script = parse(source).unwrap();
+        assert_eq!(script.body, source);
+        assert_eq!(script.manifest.unwrap()["dependencies"]["itoa"].as_str(), Some("1"));
+        // Attributes are not a #! line, and the block must come before the code
+        assert_eq!(parse("#![allow(unused)]\n//! ```cargo\n//! [dependencies]\n//! ```\n").unwrap().manifest, None);
+        assert_eq!(parse("fn main() {}\n//! ```cargo\n//! ```\n").unwrap().manifest, None);
+    }
+
+    #[test]
+    fn manifests_fix_the_package_and_limit_the_tables() {
+        let embedded: toml::Table = "package = { edition = \"2024\" }\n[dependencies]\nitoa = \"1\"".parse().unwrap();
+        let written: toml::Table = manifest(Some(&embedded)).unwrap().parse().unwrap();
+        assert_eq!(written["package"]["name"].as_str(), Some(PACKAGE_NAME));
+        assert_eq!(written["package"]["edition"].as_str(), Some("2024"));
+        assert_eq!(written["dependencies"]["itoa"].as_str(), Some("1"));
+
+        let defaults: toml::Table = manifest(None).unwrap().parse().unwrap();
+        assert_eq!(defaults["package"]["edition"].as_str(), Some(DEFAULT_EDITION));
+
+        for rejected in ["[workspace]", "[[bin]]\nname = \"x\"", "[package]\nbuild = \"build.rs\""] {
+            assert!(manifest(Some(&rejected.parse().unwrap())).is_err(), "{}", rejected);
+        }
+    }
+
+    #[test]
+    fn dependencies_go_either_in_the_source_or_the_request() {
+        let mut dependencies = toml::Table::new();
+        dependencies.insert("itoa".to_string(), "1".into());
+        let source = with_dependencies("fn main() {}\n", &dependencies).unwrap();
+        let script = parse(&source).unwrap();
+        assert_eq!(script.manifest.unwrap()["dependencies"]["itoa"].as_str(), Some("1"));
+        assert!(with_dependencies(&source, &dependencies).is_err());
+    }
+}
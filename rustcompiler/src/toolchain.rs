@@ -0,0 +1,172 @@
+// Rust toolchains a project can be built with
+//
+// A project picks its toolchain with a `toolchain` field or with a
+// rust-toolchain.toml (or legacy rust-toolchain) file in the upload; anything
+// else builds with rustup's default toolchain. Only toolchains that were
+// installed through rustup when the service started are accepted, so a
+// request can never make rustup download one. Cargo is pointed at the chosen
+// toolchain with RUSTUP_TOOLCHAIN, which also overrides the project's file.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Files rustup reads the toolchain override from, in order of precedence
+const TOOLCHAIN_FILES: [&str; 2] = ["rust-toolchain.toml", "rust-toolchain"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toolchain {
+    // rustup toolchain name, e.g. stable-x86_64-unknown-linux-gnu; None when
+    // rustc is not managed by rustup
+    pub name: Option<String>,
+    // `rustc --version`, as recorded in receipts
+    pub version: String,
+    // `rustc -Vv`, with the commit hash, host and LLVM version
+    pub verbose_version: String,
+    // `release:` line of `rustc -Vv`, e.g. 1.95.0 or 1.97.0-nightly
+    pub release: String,
+}
+
+impl Toolchain {
+    // Asks rustc of the given rustup toolchain (or the default one) for its version
+    fn probe(name: Option<&str>) -> Option<Self> {
+        let mut rustc = Command::new("rustc");
+        rustc.arg("-Vv").env("RUSTUP_AUTO_INSTALL", "0");
+        if let Some(name) = name {
+            rustc.env("RUSTUP_TOOLCHAIN", name);
+        }
+        let output = rustc.output().ok().filter(|output| output.status.success())?;
+        let verbose_version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let version = verbose_version.lines().next().unwrap_or_default().to_string();
+        let release = verbose_version.lines()
+            .find_map(|line| line.strip_prefix("release:"))
+            .map(|release| release.trim().to_string())
+            .unwrap_or_default();
+        Some(Self {
+            name: name.map(str::to_string),
+            version,
+            verbose_version,
+            release,
+        })
+    }
+
+    // Environment that makes cargo and rustc use this toolchain
+    pub fn cargo_env(&self) -> Vec<(String, String)> {
+        match &self.name {
+            Some(name) => vec![
+                ("RUSTUP_TOOLCHAIN".to_string(), name.clone()),
+                ("RUSTUP_AUTO_INSTALL".to_string(), "0".to_string()),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InstalledToolchains {
+    pub default: Toolchain,
+    pub installed: Vec<Toolchain>,
+    host: String,
+}
+
+impl InstalledToolchains {
+    // Lists the rustup toolchains and probes each of them once. Without
+    // rustup, only the rustc on PATH is available.
+    pub fn detect() -> Result<Self, String> {
+        let listed = Command::new("rustup")
+            .args(["toolchain", "list"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default();
+
+        let mut default_name = None;
+        let mut installed = Vec::new();
+        for line in listed.lines() {
+            // stable-x86_64-unknown-linux-gnu (active, default)
+            let Some(name) = line.split_whitespace().next() else {
+                continue;
+            };
+            if line.contains("default") {
+                default_name = Some(name.to_string());
+            }
+            if let Some(toolchain) = Toolchain::probe(Some(name)) {
+                installed.push(toolchain);
+            }
+        }
+
+        let default = match default_name {
+            Some(name) => installed.iter().find(|toolchain| toolchain.name.as_deref() == Some(name.as_str())).cloned(),
+            None => Toolchain::probe(None),
+        }
+        .ok_or_else(|| "No usable Rust toolchain found".to_string())?;
+        let host = default.verbose_version.lines()
+            .find_map(|line| line.strip_prefix("host:"))
+            .map(|host| host.trim().to_string())
+            .unwrap_or_default();
+        Ok(Self { default, installed, host })
+    }
+
+    // Names of the installed toolchains, for error messages
+    pub fn names(&self) -> Vec<String> {
+        self.installed.iter().filter_map(|toolchain| toolchain.name.clone()).collect()
+    }
+
+    // Installed toolchain for a requested name, the way rustup would resolve
+    // it: `stable`, `nightly-2026-05-19` or a full name with the host triple.
+    // A version such as `1.95.0` or `1.95` also matches an installed channel
+    // that is exactly that release, e.g. `stable` at 1.95.0.
+    pub fn find(&self, requested: &str) -> Option<&Toolchain> {
+        let with_host = format!("{}-{}", requested, self.host);
+        let by_name = self.installed.iter().find(|toolchain| {
+            toolchain.name.as_deref().is_some_and(|name| name == requested || name == with_host)
+        });
+        let is_version = requested.starts_with(|c: char| c.is_ascii_digit());
+        by_name.or_else(|| {
+            is_version.then(|| self.installed.iter().find(|toolchain| {
+                toolchain.release == requested || toolchain.release.strip_prefix(requested).is_some_and(|rest| rest.starts_with('.'))
+            })).flatten()
+        })
+    }
+}
+
+// Rejects names that rustup would not accept as a toolchain
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid toolchain name '{}'", name))
+    }
+}
+
+// The toolchain file of a project, if it has one
+pub fn toolchain_file(project_dir: &Path) -> Option<PathBuf> {
+    TOOLCHAIN_FILES.iter()
+        .map(|name| project_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// Channel named by a toolchain file: `[toolchain] channel = "..."` in TOML,
+// or the bare channel name in a legacy rust-toolchain file. Components and
+// targets are not installed on demand and are ignored.
+pub fn channel_from_file(text: &str) -> Result<String, String> {
+    let trimmed = text.trim();
+    let channel = if !trimmed.contains('\n') && !trimmed.contains('=') && !trimmed.starts_with('[') {
+        trimmed.to_string()
+    } else {
+        let file: toml::Value = trimmed.parse().map_err(|e| format!("Invalid toolchain file: {}", e))?;
+        let toolchain = file.get("toolchain").ok_or_else(|| "Toolchain file has no [toolchain] table".to_string())?;
+        if toolchain.get("path").is_some() {
+            return Err("Toolchain files with a custom `path` are not supported".to_string());
+        }
+        toolchain.get("channel")
+            .and_then(|channel| channel.as_str())
+            .ok_or_else(|| "Toolchain file has no channel".to_string())?
+            .to_string()
+    };
+    validate_name(&channel)?;
+    Ok(channel)
+}
@@ -1,25 +1,333 @@
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use rust_tee_compiler::archive::{ExtractError, RejectReason};
+use rust_tee_compiler::diagnostics::Diagnostic;
+use rust_tee_compiler::limits::{LimitKind, LimitOverrides};
+use rust_tee_compiler::lockfile::LockfileProblem;
+use rust_tee_compiler::metadata::RunnableTarget;
+use rust_tee_compiler::registry::MissingDependency;
+
+use crate::outcome::Outcome;
+
 // Error types
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
 pub enum CompilerApiError {
     InvalidInput { message: String },
     FileSystemError { message: String },
-    ExecutionError { message: String },
+    // `diagnostics` holds the warnings of a build that failed for another
+    // reason than a compile error
+    ExecutionError {
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        diagnostics: Vec<Diagnostic>,
+    },
     PanicError { message: String },
     InternalError { message: String },
-    WalrusApiError { message: String },
+    LimitExceeded { limit: LimitKind, message: String },
+    Cancelled { message: String },
+    PolicyViolation { message: String, violations: Vec<String> },
+    InvalidArchive { reason: RejectReason, entry: String, message: String },
+    BuildNotFound { source_sha256: String, message: String },
+    MissingDependencies { message: String, missing: Vec<MissingDependency> },
+    LockfileError {
+        problem: LockfileProblem,
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        diagnostics: Vec<Diagnostic>,
+    },
+    ToolchainUnavailable { requested: String, installed: Vec<String>, message: String },
+    InvalidTarget { message: String, available: Vec<RunnableTarget> },
+    CompileError { message: String, diagnostics: Vec<Diagnostic> },
+    AttestationUnavailable { message: String },
+}
+
+impl CompilerApiError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            CompilerApiError::InvalidInput { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::FileSystemError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            CompilerApiError::ExecutionError { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::PanicError { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            CompilerApiError::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            CompilerApiError::LimitExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            CompilerApiError::Cancelled { .. } => StatusCode::CONFLICT,
+            CompilerApiError::PolicyViolation { .. } => StatusCode::FORBIDDEN,
+            CompilerApiError::InvalidArchive { reason, .. } if reason.is_size_limit() => StatusCode::PAYLOAD_TOO_LARGE,
+            CompilerApiError::InvalidArchive { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::BuildNotFound { .. } => StatusCode::NOT_FOUND,
+            CompilerApiError::MissingDependencies { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            CompilerApiError::LockfileError { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::ToolchainUnavailable { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            CompilerApiError::InvalidTarget { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::CompileError { .. } => StatusCode::BAD_REQUEST,
+            CompilerApiError::AttestationUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CompilerApiError::InvalidInput { message }
+            | CompilerApiError::FileSystemError { message }
+            | CompilerApiError::ExecutionError { message, .. }
+            | CompilerApiError::PanicError { message }
+            | CompilerApiError::InternalError { message }
+            | CompilerApiError::LimitExceeded { message, .. }
+            | CompilerApiError::Cancelled { message }
+            | CompilerApiError::PolicyViolation { message, .. }
+            | CompilerApiError::InvalidArchive { message, .. }
+            | CompilerApiError::BuildNotFound { message, .. }
+            | CompilerApiError::MissingDependencies { message, .. }
+            | CompilerApiError::LockfileError { message, .. }
+            | CompilerApiError::ToolchainUnavailable { message, .. }
+            | CompilerApiError::InvalidTarget { message, .. }
+            | CompilerApiError::CompileError { message, .. }
+            | CompilerApiError::AttestationUnavailable { message } => message,
+        }
+    }
+}
+
+// Handler-level errors are (StatusCode, String) pairs using 400 or 500
+impl From<(StatusCode, String)> for CompilerApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        if status.is_client_error() {
+            CompilerApiError::InvalidInput { message }
+        } else {
+            CompilerApiError::InternalError { message }
+        }
+    }
+}
+
+impl From<ExtractError> for CompilerApiError {
+    fn from(error: ExtractError) -> Self {
+        match error {
+            ExtractError::Rejected { reason, entry, message } => CompilerApiError::InvalidArchive { reason, entry, message },
+            ExtractError::Io(message) => CompilerApiError::FileSystemError { message },
+        }
+    }
+}
+
+impl IntoResponse for CompilerApiError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), axum::Json(self)).into_response()
+    }
+}
+
+// A failed job. Runs also report what the build and the program produced up
+// to the failure, in the same fields as a successful ExecutionResponse.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JobError {
+    #[serde(flatten)]
+    pub error: CompilerApiError,
+    // Empty for the phases that never ran
+    #[serde(flatten)]
+    pub report: RunReport,
+}
+
+impl From<CompilerApiError> for JobError {
+    fn from(error: CompilerApiError) -> Self {
+        Self { error, report: RunReport::default() }
+    }
+}
+
+impl From<(StatusCode, String)> for JobError {
+    fn from(error: (StatusCode, String)) -> Self {
+        CompilerApiError::from(error).into()
+    }
+}
+
+impl JobError {
+    // The outcome decides the status once the project was compiled or run,
+    // unless the program succeeded and the job failed after it, e.g. on the quote
+    pub fn status_code(&self) -> StatusCode {
+        match &self.report.outcome {
+            Some(outcome) if *outcome != Outcome::Success => outcome.status_code(),
+            _ => self.error.status_code(),
+        }
+    }
+}
+
+impl IntoResponse for JobError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status_code(), axum::Json(self)).into_response()
+    }
+}
+
+// Everything a run produced, whether or not it succeeded
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct RunReport {
+    // None when the request failed before compiling, e.g. on a policy
+    #[serde(default)]
+    pub outcome: Option<Outcome>,
+    // cargo output; empty when the binary came from the artifact cache
+    #[serde(default)]
+    pub build_log: String,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    // How the program ended: an exit code, or the signal that killed it.
+    // Both are null when it never started.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub signal: Option<i32>,
+    #[serde(default)]
+    pub build_duration_ms: u64,
+    #[serde(default)]
+    pub run_duration_ms: u64,
+    // Largest resident set of the program or any process it waited for
+    #[serde(default)]
+    pub peak_rss_bytes: Option<u64>,
 }
 
 // Response types
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ExecutionResponse {
     pub status: String,
     pub output: String,
+    // Base64 TDX quote whose report data commits to the tarball, args and output
+    pub quote: String,
+    pub receipt: rust_tee_compiler::receipt::SignedReceipt,
+    // Hex SHA-256 of the normalized source tree, usable with /execute
+    pub source_sha256: String,
+    // Whether the binary came from the artifact cache instead of cargo
+    pub cached_build: bool,
+    // Hex SHA-256 of the Cargo.lock the binary was built with
+    pub lockfile_sha256: Option<String>,
+    // Hex SHA-256 of the executable, for reproducible builds
+    pub binary_sha256: Option<String>,
+    // `rustc -Vv` of the toolchain the binary was built with
+    pub rustc_version: String,
+    #[serde(flatten)]
+    pub report: RunReport,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BuildResponse {
+    pub status: String,
+    pub source_sha256: String,
+    pub cached_build: bool,
+    pub toolchain: String,
+    pub lockfile_sha256: Option<String>,
+    pub binary_sha256: Option<String>,
+    pub rustc_version: String,
+}
+
+// Result of a finished job: a run, or a build on its own
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum JobResponse {
+    Execution(Box<ExecutionResponse>),
+    Build(BuildResponse),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AttestationKeyResponse {
+    pub algorithm: String,
+    // Base64 Ed25519 key that signs execution receipts
+    pub public_key: String,
+    // Base64 TDX quote whose report data commits to the public key
     pub quote: String,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct JobCreatedResponse {
+    pub job_id: String,
+    pub state: crate::jobs::JobState,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct JobStatusResponse {
+    pub job_id: String,
+    pub user_id: String,
+    pub project_id: String,
+    pub state: crate::jobs::JobState,
+    pub created_at: String,
+    pub logs: Vec<crate::jobs::LogLine>,
+    pub result: Option<JobResponse>,
+    pub error: Option<JobError>,
+}
+
+// Program input as JSON, so arguments keep their spaces and quoting. Either
+// `argv` is passed as is, or `params` is mapped onto argv: every key except
+// POSITIONAL_PARAM becomes `--key value`, once per value and in key order
+// (`--key` alone when it has no values), followed by the positional values.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct RunRequest {
+    #[serde(default)]
+    pub params: std::collections::HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub argv: Option<Vec<String>>,
+}
+
+// Key of `params` that holds the positional arguments
+pub const POSITIONAL_PARAM: &str = "_";
+
+impl RunRequest {
+    pub fn argv(&self) -> Result<Vec<String>, String> {
+        if let Some(argv) = &self.argv {
+            if !self.params.is_empty() {
+                return Err("Pass either params or argv, not both".to_string());
+            }
+            return Ok(argv.clone());
+        }
+
+        let mut keys: Vec<&String> = self.params.keys().filter(|key| *key != POSITIONAL_PARAM).collect();
+        keys.sort();
+        let mut argv = Vec::new();
+        for key in keys {
+            let valid = !key.starts_with('-')
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if key.is_empty() || !valid {
+                return Err(format!("Invalid parameter name '{}'", key));
+            }
+            let flag = format!("--{}", key);
+            let values = &self.params[key];
+            if values.is_empty() {
+                argv.push(flag.clone());
+            }
+            for value in values {
+                argv.push(flag.clone());
+                argv.push(value.clone());
+            }
+        }
+        if let Some(positional) = self.params.get(POSITIONAL_PARAM) {
+            argv.extend(positional.iter().cloned());
+        }
+        Ok(argv)
+    }
+}
+
+// Body of /run/{user_id}/{project_id}/source: a single main.rs instead of an
+// archive. Dependencies go either here or in a manifest embedded in the source.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceSubmission {
+    pub source: String,
+    #[serde(default)]
+    pub dependencies: Option<toml::Table>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Mapped onto argv like RunRequest.params, instead of `args`
+    #[serde(default)]
     pub params: std::collections::HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+    // Input file name to contents
+    #[serde(default)]
+    pub files: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    pub limits: LimitOverrides,
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
 }
 
 // Walrus Storage API types
@@ -166,4 +474,34 @@ impl WalrusClient {
             Err(format!("Get info failed: {}", error_response.error))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> RunRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn params_map_to_sorted_flags_then_positionals() {
+        let request = request(r#"{"params": {"_": ["in.txt", "-"], "verbose": [], "n": ["10", "20"], "log.level": ["debug"]}}"#);
+        assert_eq!(request.argv().unwrap(), ["--log.level", "debug", "--n", "10", "--n", "20", "--verbose", "in.txt", "-"]);
+    }
+
+    #[test]
+    fn argv_is_passed_as_is() {
+        assert_eq!(request(r#"{"argv": ["--x", "a b", ""]}"#).argv().unwrap(), ["--x", "a b", ""]);
+        assert_eq!(request("{}").argv().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_unsafe_or_conflicting_params() {
+        assert!(request(r#"{"argv": ["1"], "params": {"n": ["1"]}}"#).argv().is_err());
+        for key in ["", "-n", "a b", "n=1", "ü"] {
+            let request = RunRequest { params: [(key.to_string(), vec!["1".to_string()])].into(), argv: None };
+            assert!(request.argv().is_err(), "{:?}", key);
+        }
+        assert!(serde_json::from_str::<RunRequest>(r#"{"args": ["1"]}"#).is_err());
+    }
+}
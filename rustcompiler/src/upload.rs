@@ -0,0 +1,272 @@
+// Streams an uploaded archive from the multipart body into a staging directory
+//
+// Chunks are handed to a blocking extractor through a channel as they arrive,
+// so nothing is buffered or written to disk in compressed form. The staging
+// directory only replaces the project directory once the whole upload has
+// been read and the ids are known.
+
+use axum::body::Bytes;
+use axum::extract::multipart::Field;
+use axum::http::StatusCode;
+use rust_tee_compiler::archive::{self, ArchiveFormat, ExtractLimits, RejectReason};
+use rust_tee_compiler::receipt::sha256_hex;
+use rust_tee_compiler::sandbox::{self, BindMount};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, OwnedMutexGuard};
+
+use crate::types::CompilerApiError;
+
+const STAGING_DIR: &str = "./projects/.staging";
+const INPUTS_DIR: &str = "./projects/.inputs";
+
+// An extracted upload waiting to be moved into place; removed if dropped
+pub struct StagedArchive {
+    dir: Option<PathBuf>,
+    // None for sources submitted as JSON
+    pub format: Option<ArchiveFormat>,
+    pub sha256: [u8; 32],
+}
+
+impl StagedArchive {
+    // Replaces `project_dir` with the staged tree, keeping the previous
+    // target/ directory so rebuilds stay incremental
+    pub async fn install(mut self, project_dir: &Path) -> Result<(), (StatusCode, String)> {
+        let staged = self.dir.take().expect("staged archive installed twice");
+        let project_dir = project_dir.to_path_buf();
+
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let old_target = project_dir.join("target");
+            let new_target = staged.join("target");
+            if old_target.is_dir() && std::fs::symlink_metadata(&new_target).is_err() {
+                std::fs::rename(&old_target, &new_target)?;
+            }
+            if std::fs::symlink_metadata(&project_dir).is_ok() {
+                std::fs::remove_dir_all(&project_dir)?;
+            }
+            if let Some(parent) = project_dir.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(&staged, &project_dir)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Install task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to install project: {}", e)))
+    }
+}
+
+// One lock per project directory. An upload holds it from installing its
+// sources until their build is cached, so a later upload of the same project
+// waits instead of swapping the sources under a queued or running build.
+#[derive(Default)]
+pub struct ProjectLocks {
+    locks: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl ProjectLocks {
+    pub async fn lock(&self, project_dir: &Path) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Nobody holds or waits for these any more
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(project_dir.to_path_buf()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+impl Drop for StagedArchive {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// Blocking reader over the chunks sent by the request handler
+struct ChunkReader {
+    receiver: mpsc::Receiver<Bytes>,
+    current: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+pub async fn stage_archive(mut field: Field<'_>, limits: &ExtractLimits) -> Result<StagedArchive, CompilerApiError> {
+    let dir = PathBuf::from(STAGING_DIR).join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create staging directory: {}", e)))?;
+    // Cleans up the staging directory on every early return below
+    let mut staged = StagedArchive {
+        dir: Some(dir.clone()),
+        format: None,
+        sha256: [0; 32],
+    };
+
+    let (sender, receiver) = mpsc::channel::<Bytes>(16);
+    let extract_limits = limits.clone();
+    let extraction = tokio::task::spawn_blocking(move || {
+        let reader = ChunkReader { receiver, current: Bytes::new() };
+        archive::extract(reader, &dir, &extract_limits)
+    });
+
+    let mut hasher = Sha256::new();
+    let mut received: u64 = 0;
+    let mut read_error = None;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                read_error = Some(format!("Failed to read field data: {}", e));
+                break;
+            }
+        };
+        received += chunk.len() as u64;
+        if received > limits.max_total_bytes {
+            read_error = Some(format!("Upload exceeds {} bytes", limits.max_total_bytes));
+            break;
+        }
+        hasher.update(&chunk);
+        // The extractor may stop before the end (trailing padding, or an
+        // error); the rest is still read so the hash covers the whole upload
+        let _ = sender.send(chunk).await;
+    }
+    drop(sender);
+    println!("Field data size: {} bytes", received);
+
+    let result = extraction
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Extraction task failed: {}", e)))?;
+    if let Some(message) = read_error {
+        if received > limits.max_total_bytes {
+            return Err(CompilerApiError::InvalidArchive {
+                reason: RejectReason::TotalSizeExceeded,
+                entry: String::new(),
+                message,
+            });
+        }
+        return Err((StatusCode::BAD_REQUEST, message).into());
+    }
+
+    staged.format = Some(result?);
+    staged.sha256 = hasher.finalize().into();
+    Ok(staged)
+}
+
+// Stages a single source file as a loose upload, for submissions without an
+// archive. `sha256` identifies the submission in receipts.
+pub async fn stage_source(file_name: &str, source: &str, sha256: [u8; 32]) -> Result<StagedArchive, CompilerApiError> {
+    let dir = PathBuf::from(STAGING_DIR).join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create staging directory: {}", e)))?;
+    let staged = StagedArchive {
+        dir: Some(dir.clone()),
+        format: None,
+        sha256,
+    };
+    tokio::fs::write(dir.join(file_name), source)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write {}: {}", file_name, e)))?;
+    Ok(staged)
+}
+
+// Input files for one execution, kept apart from the project so builds and
+// the artifact cache never see them; removed if dropped
+#[derive(Default)]
+pub struct StagedInputs {
+    dir: Option<PathBuf>,
+    // File name to SHA-256, as recorded in receipts
+    pub files: BTreeMap<String, String>,
+}
+
+impl StagedInputs {
+    // Stores one read-only input file; names are plain file names
+    pub async fn add(&mut self, name: &str, contents: &[u8], limits: &ExtractLimits) -> Result<(), CompilerApiError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name.len() <= 128
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+        if !valid {
+            return Err((StatusCode::BAD_REQUEST, format!("Invalid input file name '{}'", name)).into());
+        }
+        if self.files.contains_key(name) {
+            return Err((StatusCode::BAD_REQUEST, format!("Input file '{}' given twice", name)).into());
+        }
+        if self.files.len() as u64 >= limits.max_entries {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("More than {} input files", limits.max_entries)).into());
+        }
+        if contents.len() as u64 > limits.max_file_bytes {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Input file '{}' exceeds {} bytes", name, limits.max_file_bytes)).into());
+        }
+
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = PathBuf::from(INPUTS_DIR).join(uuid::Uuid::new_v4().to_string());
+                tokio::fs::create_dir_all(&dir)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create input directory: {}", e)))?;
+                // tee-exec runs elsewhere, so mounts need the absolute path
+                let dir = tokio::fs::canonicalize(&dir)
+                    .await
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resolve input directory: {}", e)))?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        let path = dir.join(name);
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write input file {}: {}", name, e)))?;
+        let read_only = std::os::unix::fs::PermissionsExt::from_mode(0o444);
+        tokio::fs::set_permissions(&path, read_only)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to protect input file {}: {}", name, e)))?;
+        self.files.insert(name.to_string(), sha256_hex(contents));
+        Ok(())
+    }
+
+    // Directory holding the files, once there is one
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    // Read-only mounts of the files into the sandbox working directory
+    pub fn mounts(&self) -> Vec<BindMount> {
+        let Some(dir) = &self.dir else {
+            return Vec::new();
+        };
+        self.files.keys()
+            .map(|name| BindMount {
+                source: dir.join(name),
+                target: Path::new(sandbox::WORKDIR).join(name),
+                writable: false,
+            })
+            .collect()
+    }
+}
+
+impl Drop for StagedInputs {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+// Offline verification of run_project results
+//
+// Takes the JSON returned by /run (and optionally /attestation/pubkey) plus
+// the original tarball (or source hash, for /execute) and args, recomputes
+// every commitment and reports a pass/fail result per check. Nothing here
+// talks to the server.
+
+use crate::attestation::{execution_report_data_from_digest, receipt_key_report_data, ExecutedCode, ExecutionInputs, TdxQuote};
+use crate::receipt::{self, SignedReceipt};
+use base64::Engine;
+use std::collections::BTreeMap;
+
+// Subset of the /run response needed for verification
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ExecutionEvidence {
+    pub output: String,
+    pub quote: String,
+    pub receipt: SignedReceipt,
+}
+
+// Subset of the /attestation/pubkey response
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct KeyAttestation {
+    pub public_key: String,
+    pub quote: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct VerificationReport {
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl VerificationReport {
+    fn push(&mut self, name: &str, result: Result<String, String>) {
+        let (status, detail) = match result {
+            Ok(detail) => (CheckStatus::Pass, detail),
+            Err(detail) => (CheckStatus::Fail, detail),
+        };
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            status,
+            detail,
+        });
+    }
+
+    fn skip(&mut self, name: &str, detail: &str) {
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Skip,
+            detail: detail.to_string(),
+        });
+    }
+}
+
+pub struct VerifyOptions<'a> {
+    // SHA-256 of the tarball, or of the source tree for /execute receipts
+    pub code: ExecutedCode,
+    pub args: &'a [String],
+    // Cargo.lock to pin the dependencies to, if the caller has one
+    pub lockfile: Option<&'a [u8]>,
+    // Executable rebuilt locally from the tarball in reproducible mode
+    pub binary: Option<&'a [u8]>,
+    // Program input of the run: stdin, environment and named input files
+    pub stdin: Option<&'a [u8]>,
+    pub env: &'a BTreeMap<String, String>,
+    pub input_files: &'a [(String, Vec<u8>)],
+    pub expected_measurements: &'a ExpectedMeasurements,
+    // Without it the receipt signature cannot be trusted and verification fails
+    pub key_attestation: Option<&'a KeyAttestation>,
+}
+
+// TD measurements the quotes must carry, as hex. They are compared with the
+// registers in the quote as they are; deriving a ROFL enclave identity from
+// them also involves the TD attributes, which the quote parser doesn't read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedMeasurements {
+    pub mr_td: Option<String>,
+    pub rtmr: [Option<String>; 4],
+}
+
+impl ExpectedMeasurements {
+    pub fn is_empty(&self) -> bool {
+        self.mr_td.is_none() && self.rtmr.iter().all(Option::is_none)
+    }
+}
+
+fn decode_quote(quote: &str) -> Result<TdxQuote, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(quote)
+        .map_err(|e| format!("Invalid quote encoding: {}", e))?;
+    TdxQuote::parse(&bytes)
+}
+
+fn check_measurement(quote: &TdxQuote, expected: &ExpectedMeasurements) -> Result<String, String> {
+    let registers = std::iter::once(("MRTD".to_string(), &expected.mr_td, &quote.mr_td))
+        .chain((0..4).map(|index| (format!("RTMR{}", index), &expected.rtmr[index], &quote.rtmr[index])));
+    let mut checked = Vec::new();
+    for (name, expected, actual) in registers {
+        let Some(expected) = expected else { continue };
+        let actual = hex::encode(actual);
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(format!("{} {} does not match the expected {}", name, actual, expected));
+        }
+        checked.push(name);
+    }
+    Ok(format!("{} match the expected values", checked.join(", ")))
+}
+
+fn check_hash(what: &str, sha256: &[u8; 32], recorded: &Option<String>) -> Result<String, String> {
+    let hash = hex::encode(sha256);
+    match recorded {
+        Some(expected) if *expected == hash => Ok(format!("{} SHA-256 {} matches the receipt", what, hash)),
+        Some(expected) => Err(format!("{} SHA-256 {} does not match receipt {}", what, hash, expected)),
+        None => Err(format!("Receipt does not record a {} SHA-256", what.to_lowercase())),
+    }
+}
+
+pub fn verify_execution(evidence: &ExecutionEvidence, options: &VerifyOptions) -> VerificationReport {
+    let mut report = VerificationReport {
+        passed: false,
+        checks: Vec::new(),
+    };
+    let receipt = &evidence.receipt.receipt;
+
+    match options.code {
+        ExecutedCode::Tarball(sha256) => report.push("tarball_hash", check_hash("Tarball", &sha256, &receipt.tarball_sha256)),
+        ExecutedCode::Source(sha256) => report.push("source_hash", check_hash("Source", &sha256, &receipt.source_sha256)),
+    }
+
+    report.push("args", if options.args == receipt.args.as_slice() {
+        Ok(format!("Args {:?} match the receipt", options.args))
+    } else {
+        Err(format!("Args {:?} do not match receipt {:?}", options.args, receipt.args))
+    });
+
+    let stdin_hash = options.stdin.map(receipt::sha256_hex);
+    let input_hashes: BTreeMap<String, String> = options.input_files.iter()
+        .map(|(name, contents)| (name.clone(), receipt::sha256_hex(contents)))
+        .collect();
+    report.push("inputs", if stdin_hash != receipt.stdin_sha256 {
+        Err(format!("Stdin SHA-256 {:?} does not match receipt {:?}", stdin_hash, receipt.stdin_sha256))
+    } else if *options.env != receipt.env {
+        Err(format!("Environment {:?} does not match receipt {:?}", options.env, receipt.env))
+    } else if input_hashes != receipt.input_files {
+        Err(format!("Input files {:?} do not match receipt {:?}", input_hashes, receipt.input_files))
+    } else {
+        Ok("Stdin, environment and input files match the receipt".to_string())
+    });
+
+    match options.lockfile {
+        Some(lockfile) => {
+            let lockfile_hash = receipt::sha256_hex(lockfile);
+            report.push("lockfile_hash", match &receipt.lockfile_sha256 {
+                Some(expected) if *expected == lockfile_hash => Ok(format!("Cargo.lock SHA-256 {} matches the receipt", lockfile_hash)),
+                Some(expected) => Err(format!("Cargo.lock SHA-256 {} does not match receipt {}", lockfile_hash, expected)),
+                None => Err("Receipt does not record a Cargo.lock".to_string()),
+            });
+        }
+        None => report.skip("lockfile_hash", "No lockfile given"),
+    }
+
+    match options.binary {
+        Some(binary) => {
+            let binary_hash = receipt::sha256_hex(binary);
+            report.push("binary_hash", match &receipt.binary_sha256 {
+                Some(expected) if *expected == binary_hash => Ok(format!("Executable SHA-256 {} matches the receipt", binary_hash)),
+                Some(expected) => Err(format!("Executable SHA-256 {} does not match receipt {}", binary_hash, expected)),
+                None => Err("Receipt does not record an executable; the build was not reproducible".to_string()),
+            });
+        }
+        None => report.skip("binary_hash", "No executable given"),
+    }
+
+    report.push("output", if receipt.stdout.trim() == evidence.output {
+        Ok("Response output matches the receipt stdout".to_string())
+    } else {
+        Err("Response output does not match the receipt stdout".to_string())
+    });
+
+    // The key inside the receipt proves nothing, anyone can sign with their own
+    match options.key_attestation {
+        Some(attestation) => {
+            let trusted_key = attestation.public_key.as_str();
+            report.push("signature", receipt::verify_signature(&evidence.receipt, trusted_key)
+                .map(|_| format!("Receipt signed by {}", trusted_key)));
+        }
+        None => report.skip("signature", "No key attestation given; the receipt's own public key is not trusted"),
+    }
+
+    match decode_quote(&evidence.quote) {
+        Ok(quote) => {
+            report.push("quote_structure", Ok(format!("TDX quote v{}", quote.version)));
+
+            let inputs = ExecutionInputs {
+                stdin_sha256: stdin_hash.as_deref(),
+                env: options.env,
+                input_files: &input_hashes,
+            };
+            let expected = execution_report_data_from_digest(&options.code, options.args, &inputs, receipt.stdout.as_bytes());
+            report.push("report_data", if quote.report_data == expected {
+                Ok("Quote report data commits to the code, args, inputs and receipt stdout".to_string())
+            } else {
+                Err("Quote report data does not match the recomputed commitment".to_string())
+            });
+
+            if options.expected_measurements.is_empty() {
+                report.skip("measurement", "No expected measurements given");
+            } else {
+                report.push("measurement", check_measurement(&quote, options.expected_measurements));
+            }
+        }
+        Err(e) => {
+            report.push("quote_structure", Err(e));
+            report.skip("report_data", "Quote could not be parsed");
+            report.skip("measurement", "Quote could not be parsed");
+        }
+    }
+
+    match options.key_attestation {
+        Some(attestation) => report.push("key_binding", verify_key_binding(attestation, options.expected_measurements)),
+        None => report.skip("key_binding", "No key attestation given"),
+    }
+
+    report.passed = options.key_attestation.is_some()
+        && report.checks.iter().all(|check| check.status != CheckStatus::Fail);
+    report
+}
+
+fn verify_key_binding(attestation: &KeyAttestation, expected: &ExpectedMeasurements) -> Result<String, String> {
+    let public_key = base64::engine::general_purpose::STANDARD
+        .decode(&attestation.public_key)
+        .map_err(|e| format!("Invalid public key encoding: {}", e))?;
+    let quote = decode_quote(&attestation.quote)?;
+
+    if quote.report_data != receipt_key_report_data(&public_key) {
+        return Err("Key quote report data does not commit to the public key".to_string());
+    }
+    if !expected.is_empty() {
+        check_measurement(&quote, expected)?;
+    }
+    Ok(format!("Public key {} is bound to the key quote", attestation.public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::{execution_report_data, AttestationProvider, MockProvider};
+    use crate::receipt::{ExecutionReceipt, ReceiptSigner, RECEIPT_VERSION};
+    use sha2::{Digest, Sha256};
+
+    const TARBALL: &[u8] = b"tarball";
+
+    fn quote(report_data: &[u8; 64]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(MockProvider.get_quote(report_data).unwrap())
+    }
+
+    fn evidence(signer: &ReceiptSigner, args: &[String], env: &BTreeMap<String, String>, output: &str) -> ExecutionEvidence {
+        let receipt = ExecutionReceipt {
+            version: RECEIPT_VERSION,
+            tarball_sha256: Some(receipt::sha256_hex(TARBALL)),
+            source_sha256: None,
+            args: args.to_vec(),
+            stdout: format!("{}\n", output),
+            exit_code: 0,
+            started_at: "2026-01-01T00:00:00Z".to_string(),
+            finished_at: "2026-01-01T00:00:01Z".to_string(),
+            toolchain: "1.95.0".to_string(),
+            lockfile_sha256: Some(receipt::sha256_hex(b"lockfile")),
+            binary_sha256: None,
+            stdin_sha256: None,
+            env: env.clone(),
+            input_files: BTreeMap::new(),
+        };
+        let inputs = ExecutionInputs { stdin_sha256: None, env, input_files: &receipt.input_files };
+        ExecutionEvidence {
+            output: output.to_string(),
+            quote: quote(&execution_report_data(TARBALL, args, &inputs, receipt.stdout.as_bytes())),
+            receipt: signer.sign(receipt).unwrap(),
+        }
+    }
+
+    fn key_attestation(signer: &ReceiptSigner) -> KeyAttestation {
+        KeyAttestation {
+            public_key: base64::engine::general_purpose::STANDARD.encode(signer.public_key()),
+            quote: quote(&receipt_key_report_data(&signer.public_key())),
+        }
+    }
+
+    fn status(report: &VerificationReport, name: &str) -> CheckStatus {
+        report.checks.iter().find(|check| check.name == name).unwrap().status
+    }
+
+    fn options<'a>(args: &'a [String], env: &'a BTreeMap<String, String>, measurements: &'a ExpectedMeasurements, key: Option<&'a KeyAttestation>) -> VerifyOptions<'a> {
+        VerifyOptions {
+            code: ExecutedCode::Tarball(Sha256::digest(TARBALL).into()),
+            args,
+            lockfile: Some(b"lockfile"),
+            binary: None,
+            stdin: None,
+            env,
+            input_files: &[],
+            expected_measurements: measurements,
+            key_attestation: key,
+        }
+    }
+
+    #[test]
+    fn verifies_a_mock_run_end_to_end() {
+        let signer = ReceiptSigner::generate();
+        let args = vec!["10".to_string()];
+        let env = BTreeMap::from([("RUST_LOG".to_string(), "info".to_string())]);
+        let evidence = evidence(&signer, &args, &env, "55");
+        let key = key_attestation(&signer);
+        let mock_quote = TdxQuote::parse(&MockProvider.get_quote(&[0; 64]).unwrap()).unwrap();
+        let measurements = ExpectedMeasurements {
+            mr_td: Some(hex::encode(mock_quote.mr_td)),
+            rtmr: [Some(hex::encode(mock_quote.rtmr[0])), None, None, None],
+        };
+
+        let report = verify_execution(&evidence, &options(&args, &env, &measurements, Some(&key)));
+        assert!(report.passed, "{:?}", report.checks);
+        for check in ["tarball_hash", "args", "inputs", "lockfile_hash", "output", "signature", "report_data", "measurement", "key_binding"] {
+            assert_eq!(status(&report, check), CheckStatus::Pass, "{}", check);
+        }
+        assert_eq!(status(&report, "binary_hash"), CheckStatus::Skip);
+    }
+
+    #[test]
+    fn fails_on_other_inputs_keys_or_measurements() {
+        let signer = ReceiptSigner::generate();
+        let args = vec!["10".to_string()];
+        let env = BTreeMap::new();
+        let evidence = evidence(&signer, &args, &env, "55");
+        let key = key_attestation(&signer);
+        let none = ExpectedMeasurements::default();
+
+        let other_args = vec!["11".to_string()];
+        let report = verify_execution(&evidence, &options(&other_args, &env, &none, Some(&key)));
+        assert!(!report.passed);
+        assert_eq!(status(&report, "args"), CheckStatus::Fail);
+        assert_eq!(status(&report, "report_data"), CheckStatus::Fail);
+
+        let other_env = BTreeMap::from([("RUST_LOG".to_string(), "debug".to_string())]);
+        let report = verify_execution(&evidence, &options(&args, &other_env, &none, Some(&key)));
+        assert_eq!(status(&report, "inputs"), CheckStatus::Fail);
+        assert_eq!(status(&report, "report_data"), CheckStatus::Fail);
+
+        // Signed by a key other than the attested one
+        let other_key = key_attestation(&ReceiptSigner::generate());
+        let report = verify_execution(&evidence, &options(&args, &env, &none, Some(&other_key)));
+        assert!(!report.passed);
+        assert_eq!(status(&report, "signature"), CheckStatus::Fail);
+        assert_eq!(status(&report, "key_binding"), CheckStatus::Pass);
+
+        let untrusted = ExpectedMeasurements { mr_td: Some("00".repeat(48)), ..Default::default() };
+        let report = verify_execution(&evidence, &options(&args, &env, &untrusted, Some(&key)));
+        assert!(!report.passed);
+        assert_eq!(status(&report, "measurement"), CheckStatus::Fail);
+        assert_eq!(status(&report, "key_binding"), CheckStatus::Fail);
+    }
+
+    #[test]
+    fn verifies_runs_of_cached_builds_against_the_source_hash() {
+        let signer = ReceiptSigner::generate();
+        let args = vec!["10".to_string()];
+        let env = BTreeMap::new();
+        let source = ExecutedCode::Source([3; 32]);
+        let mut receipt = evidence(&signer, &args, &env, "55").receipt.receipt;
+        receipt.tarball_sha256 = None;
+        receipt.source_sha256 = Some(hex::encode([3; 32]));
+        let inputs = ExecutionInputs { stdin_sha256: None, env: &env, input_files: &receipt.input_files };
+        let evidence = ExecutionEvidence {
+            output: "55".to_string(),
+            quote: quote(&execution_report_data_from_digest(&source, &args, &inputs, receipt.stdout.as_bytes())),
+            receipt: signer.sign(receipt).unwrap(),
+        };
+        let key = key_attestation(&signer);
+        let none = ExpectedMeasurements::default();
+
+        let report = verify_execution(&evidence, &VerifyOptions { code: source, ..options(&args, &env, &none, Some(&key)) });
+        assert!(report.passed, "{:?}", report.checks);
+        assert_eq!(status(&report, "source_hash"), CheckStatus::Pass);
+        assert_eq!(status(&report, "report_data"), CheckStatus::Pass);
+
+        // The receipt names no tarball, so none can match it
+        let report = verify_execution(&evidence, &options(&args, &env, &none, Some(&key)));
+        assert!(!report.passed);
+        assert_eq!(status(&report, "tarball_hash"), CheckStatus::Fail);
+        assert_eq!(status(&report, "report_data"), CheckStatus::Fail);
+
+        let other = ExecutedCode::Source([4; 32]);
+        let report = verify_execution(&evidence, &VerifyOptions { code: other, ..options(&args, &env, &none, Some(&key)) });
+        assert_eq!(status(&report, "source_hash"), CheckStatus::Fail);
+    }
+
+    #[test]
+    fn does_not_trust_the_key_inside_the_receipt() {
+        // A forged receipt is consistent with itself: signed by its own key
+        // over a quote with the matching report data
+        let forger = ReceiptSigner::generate();
+        let args = vec!["10".to_string()];
+        let env = BTreeMap::new();
+        let evidence = evidence(&forger, &args, &env, "55");
+
+        let report = verify_execution(&evidence, &options(&args, &env, &ExpectedMeasurements::default(), None));
+        assert!(!report.passed);
+        assert_eq!(status(&report, "signature"), CheckStatus::Skip);
+        assert!(report.checks.iter().all(|check| check.status != CheckStatus::Fail));
+    }
+}